@@ -1,7 +1,8 @@
 extern crate flossy;
 #[macro_use] extern crate clap;
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, SubCommand};
 use std::net::SocketAddr;
+use std::process;
 use std::thread;
 use std::sync::Mutex;
 
@@ -19,6 +20,18 @@ extern crate slog;
 use slog::Drain;
 use flossy::downstream::*;
 
+fn proxy_url_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("PROXY_URL")
+        .required(true)
+        .index(1)
+        .help("URL of the proxy to test.")
+}
+
+fn port_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("PORT")
+        .help("Port used by flossy's test server.")
+}
+
 fn main () {
     let decorator = slog_term::TermDecorator::new().build();
     let drain =
@@ -36,18 +49,207 @@ fn main () {
     let args = App::new(crate_name!())
       .version(crate_version!())
       .about(crate_description!())
-      .arg(Arg::with_name("PROXY_URL")
-              .required(true)
-              .index(1)
-              .help("URL of the proxy to test."))
-      .arg(Arg::with_name("PORT")
-              .help("Port used by flossy's test server."))
+      .setting(AppSettings::SubcommandsNegateReqs)
+      .arg(proxy_url_arg())
+      .arg(port_arg())
+      .arg(Arg::with_name("DELIVERY")
+              .long("delivery")
+              .takes_value(true)
+              .multiple(true)
+              .number_of_values(1)
+              .help("Send every test's request using this delivery \
+                     strategy: `whole`, `bytewise`, `crlf`, `names`, \
+                     `chunks`, or comma-separated byte offsets, optionally \
+                     followed by `@` and a delay in milliseconds between \
+                     segments. \
+                     May be given more than once, or as `all` to rerun \
+                     the tests under each named strategy."))
+      .arg(Arg::with_name("PROFILE")
+              .long("profile")
+              .takes_value(true)
+              .possible_values(&["reverse", "forward"])
+              .default_value("reverse")
+              .help("How the proxy is deployed: as a `reverse` proxy in \
+                     front of the upstream, or a `forward` proxy chosen \
+                     by its clients."))
+      .arg(Arg::with_name("TRUST_FORWARDED")
+              .long("trust-forwarded")
+              .help("Expect the proxy to keep the Forwarded, \
+                     X-Forwarded-* and X-Real-IP headers clients send and \
+                     append to them, rather than discard them."))
+      .arg(Arg::with_name("HEXDUMP")
+              .long("hexdump")
+              .help("Show the bytes the proxy sent in failure output as a \
+                     hexdump, rather than escaped text."))
       .arg(Arg::with_name("v")
               .short("v")
               .multiple(true)
               .help("Sets the level of verbosity"))
+      .subcommand(SubCommand::with_name("probe")
+              .about("Finds the size limits the proxy enforces on \
+                      requests.")
+              .arg(proxy_url_arg())
+              .arg(port_arg()))
+      .subcommand(SubCommand::with_name("explain")
+              .about("Explains what a test checks, and why.")
+              .arg(Arg::with_name("TEST")
+                      .required(true)
+                      .help("The name of the test, like \"Bad Framing 1\" \
+                             or bad-framing-1.")))
+      .subcommand(SubCommand::with_name("list")
+              .about("Lists the tests, with the RFC sections they check.")
+              .arg(Arg::with_name("TAG")
+                      .long("tag")
+                      .takes_value(true)
+                      .multiple(true)
+                      .number_of_values(1)
+                      .help("Only list tests with this tag, like `framing` \
+                             or `security`. May be given more than once.")))
       .get_matches();
 
+    // every test, in the order they're run
+    let default_tests: &[&'static Test] =
+        &[ &CONFLICTING_CONTENT_LENGTH_RESP
+         , &CONFLICTING_CONTENT_LENGTH_REQ
+         , &CONFLICTING_TRANSFER_ENCOING_REQ
+         , &LOWERCASE_METHOD
+         , &UNKNOWN_METHOD
+         , &INVALID_METHOD
+         , &UPSTREAM_REFUSED
+         , &UPSTREAM_RESET
+         , &UPSTREAM_CLOSED_AFTER_HEADERS
+         , &UPSTREAM_TRUNCATED_LENGTH
+         , &UPSTREAM_TRUNCATED_CHUNKED
+         , &UPSTREAM_NEVER_RESPONDS
+         , &DELAYED_STATUS_LINE
+         , &DELAYED_HEADERS
+         , &DELAYED_CHUNKS
+         , &STALLED_POST_NOT_RETRIED
+         , &STALLED_POST_TIMEOUT
+         , &RETRY_GET_RESET
+         , &RETRY_PUT_RESET
+         , &RETRY_DELETE_RESET
+         , &RETRY_POST_RESET
+         , &RETRY_GET_UNAVAILABLE
+         , &RETRY_PUT_UNAVAILABLE
+         , &RETRY_DELETE_UNAVAILABLE
+         , &RETRY_POST_UNAVAILABLE
+         , &REUSE_AFTER_CONNECTION_CLOSE
+         , &REUSE_AFTER_EXTRA_BYTES
+         , &REUSE_AFTER_IDLE_CLOSE
+         , &CLIENT_HALF_CLOSE
+         , &CLIENT_HALF_CLOSE_POST
+         , &UPSTREAM_HALF_CLOSE
+         , &UPSTREAM_HALF_CLOSE_UNFRAMED
+         , &CLIENT_RESET_MID_BODY
+         , &UPSTREAM_RESET_MID_LENGTH
+         , &UPSTREAM_RESET_MID_CHUNKED
+         , &LARGE_REQUEST_LENGTH
+         , &LARGE_REQUEST_CHUNKED
+         , &LARGE_RESPONSE_LENGTH
+         , &LARGE_RESPONSE_CHUNKED
+         , &LARGE_RESPONSE_STREAMED
+         , &MISSING_HOST
+         , &MULTIPLE_HOSTS
+         , &REPEATED_HOST
+         , &INVALID_HOST_CHARS
+         , &HOST_WITH_USERINFO
+         , &HOST_PORT_OUT_OF_RANGE
+         , &HOST_PORT_NOT_NUMERIC
+         , &HOST_IPV6_UNBRACKETED
+         , &HOST_TRAILING_DOT
+         , &HOST_UPPERCASE
+         , &HOST_ABSOLUTE_MISMATCH
+         , &ENCODED_DOT_SEGMENTS
+         , &ENCODED_SLASH
+         , &DOT_SEGMENTS
+         , &DOUBLE_SLASHES
+         , &QUESTION_MARKS_IN_QUERY
+         , &ENCODED_OVERLONG_UTF8
+         , &BACKSLASHES
+         , &FRAGMENT
+         , &FRAGMENT_IN_QUERY
+         , &RAW_OVERLONG_UTF8
+         , &NUL_IN_TARGET
+         , &ASTERISK_FORM_GET
+         , &OPTIONS_ASTERISK
+         , &OPTIONS_MAX_FORWARDS_0
+         , &OPTIONS_MAX_FORWARDS_1
+         , &TRACE_MAX_FORWARDS_0
+         , &TRACE_CREDENTIALS
+         , &TRACE_MAX_FORWARDS_1
+         , &TRACE_WITH_BODY
+         , &CACHE_NO_STORE
+         , &CACHE_PRIVATE
+         , &CACHE_MAX_AGE
+         , &CACHE_S_MAXAGE
+         , &CACHE_EXPIRED
+         , &CACHE_AGE
+         , &CACHE_VARY
+         , &CACHE_AUTHORIZATION
+         , &IF_NONE_MATCH_FORWARDED
+         , &IF_MODIFIED_SINCE_FORWARDED
+         , &IF_MATCH_FORWARDED
+         , &IF_RANGE_FORWARDED
+         , &NOT_MODIFIED_FROM_CACHE
+         , &MODIFIED_FROM_CACHE
+         , &WEAK_ETAG_PRESERVED
+         , &WEAK_IF_NONE_MATCH
+         , &STRONG_ETAG_PRESERVED
+         , &SINGLE_RANGE
+         , &SUFFIX_RANGE
+         , &MULTIPLE_RANGES
+         , &UNSATISFIABLE_RANGE
+         , &PARTIAL_NOT_SERVED_AS_FULL
+         , &PARTIAL_NOT_SERVED_FOR_OTHER_RANGE
+         , &GZIP_BODY
+         , &DEFLATE_BODY
+         , &BROTLI_BODY
+         , &DOUBLE_ENCODED_BODY
+         , &UNACCEPTED_ENCODING
+         , &NO_TRANSFORM
+         , &ACCEPT_ENCODING_RESPECTED
+         , &NO_CONTENT_ENCODED
+         , &HEAD_ENCODED
+         , &FORWARDED_SYNTAX
+         , &FORWARDED_APPENDED
+         , &FORWARDED_IPV6
+         , &FORWARDED_MALFORMED
+         , &X_FORWARDED_FOR_APPENDED
+         , &X_FORWARDED_FOR_MULTIPLE
+         , &X_FORWARDED_PROTO
+         , &X_REAL_IP
+         , &REQUEST_VALUE_BYTES
+         , &REQUEST_LIST_ORDER
+         , &REQUEST_DUPLICATE_ORDER
+         , &REQUEST_COOKIES
+         , &SET_COOKIE_NOT_MERGED
+         , &RESPONSE_LIST_ORDER
+         , &RESPONSE_VALUE_BYTES
+         ];
+
+    if let Some(explain) = args.subcommand_matches("explain") {
+        let name = explain.value_of("TEST").unwrap_or("");
+        match find_test(default_tests, name) {
+            Some(test) => explain_test(test)
+          , None => clap::Error::with_description(
+                &format!("no test is called {:?}", name),
+                clap::ErrorKind::InvalidValue).exit()
+        }
+        return
+    }
+    if let Some(list) = args.subcommand_matches("list") {
+        let tags = list.values_of("TAG")
+            .map(|tags| tags.collect::<Vec<_>>())
+            .unwrap_or_default();
+        list_tests(default_tests, &tags);
+        return
+    }
+    let (args, probing) = match args.subcommand_matches("probe") {
+        Some(probe) => (probe, true)
+      , None => (&args, false)
+    };
+
     let proxy_addr = value_t!(args, "PROXY_URL", SocketAddr)
         .unwrap_or_else(|e| e.exit());
     let port = value_t!(args, "port", u32).unwrap_or(7777);
@@ -59,12 +261,47 @@ fn main () {
         .spawn(move || flossy::upstream::serve(addr))
         .unwrap();
 
-    // run tests
-    let default_tests: [&'static Test; 3] =
-        [ &CONFLICTING_CONTENT_LENGTH_RESP
-        , &CONFLICTING_CONTENT_LENGTH_REQ
-        , &CONFLICTING_TRANSFER_ENCOING_REQ
-        ];
-    flossy::downstream::do_tests(&upstream_uri, &proxy_addr, &default_tests);
+    if probing {
+        flossy::downstream::probe(&upstream_uri, &proxy_addr)
+            .unwrap_or_else(|e| clap::Error::with_description(
+                &format!("couldn't probe the proxy: {}", e),
+                clap::ErrorKind::Io).exit());
+        return
+    }
+
+    let deliveries = match args.values_of("DELIVERY") {
+        None => vec![]
+      , Some(ref values) if values.clone().any(|v| v == "all") =>
+            Delivery::all()
+      , Some(values) => values
+            .map(|v| v.parse::<Delivery>()
+                      .unwrap_or_else(|e| clap::Error::with_description(
+                            &e, clap::ErrorKind::InvalidValue).exit()))
+            .collect()
+    };
+
+    let mut profile = value_t!(args, "PROFILE", Profile)
+        .unwrap_or_else(|e| e.exit());
+    if args.is_present("TRUST_FORWARDED") {
+        profile = profile.trusting();
+    }
+    let rendering = if args.is_present("HEXDUMP") { Rendering::Hexdump }
+                    else { Rendering::Escaped };
+
+    let mut summary = Summary::default();
+    if deliveries.is_empty() {
+        summary += flossy::downstream::do_tests(&upstream_uri, &proxy_addr,
+                                                default_tests, None,
+                                                &profile, rendering);
+    }
+    for delivery in &deliveries {
+        summary += flossy::downstream::do_tests(&upstream_uri, &proxy_addr,
+                                                default_tests, Some(delivery),
+                                                &profile, rendering);
+    }
 
+    // warnings and skipped tests don't fail the run, only failures do
+    if !summary.is_success() {
+        process::exit(1);
+    }
 }
@@ -0,0 +1,191 @@
+//! Tests for how the proxy handles large message bodies.
+//!
+//! A few bytes of body fit in a single read, so they don't exercise the
+//! proxy's buffering at all. These tests send multi-megabyte bodies in
+//! both directions, generated from a seeded PRNG so that corruption,
+//! truncation or reordering anywhere in them changes their checksum.
+use std::time::Duration;
+
+use upstream::{Received, Response, Script, checksum, payload};
+
+use super::{Level, RawRequest, Request, Test, Verb};
+use super::expect::{ Assertion, all_of, body_eq, complete_ok, expect
+                   , status_is, streamed, upstream_received };
+
+/// how large each test body is
+const BODY_LEN: usize = 4 << 20;
+
+/// the most writes an upload is sent in. a body of megabytes would
+/// otherwise take millions of writes under `bytewise` delivery
+const SEGMENT_LIMIT: usize = 4096;
+
+/// how large each chunk of a chunked body is
+const CHUNK_LEN: usize = 64 << 10;
+
+/// encode `body` as a chunked message body
+fn chunked(body: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(body.len() + body.len() / 1000);
+    for chunk in body.chunks(CHUNK_LEN) {
+        encoded.extend(format!("{:x}\r\n", chunk.len()).into_bytes());
+        encoded.extend_from_slice(chunk);
+        encoded.extend_from_slice(b"\r\n");
+    }
+    encoded.extend_from_slice(b"0\r\n\r\n");
+    encoded
+}
+
+/// build a request that uploads the body generated from `seed`
+fn upload(path: &'static str, seed: u64, chunked_body: bool) -> RawRequest {
+    let mut request = Request::new();
+    request.with_verb(Verb::Post)
+           .with_path(path)
+           .with_header("Connection: close");
+    let body = payload(seed, BODY_LEN);
+    if chunked_body {
+        request.with_header("Transfer-Encoding: chunked");
+    } else {
+        request.with_header(format!("Content-Length: {}", BODY_LEN));
+    }
+    let mut raw = request.to_raw();
+    raw.with_body(if chunked_body { chunked(&body) } else { body });
+    raw
+}
+
+/// the upstream recieved exactly the body generated from `seed`, and the
+/// proxy forwarded the upstream's response
+fn uploaded_intact(seed: u64) -> Assertion {
+    let expected = checksum(&payload(seed, BODY_LEN));
+    all_of(vec![ upstream_received("the whole body, unchanged",
+                     move |request: &Received|
+                         request.body.len() == BODY_LEN &&
+                         request.checksum == expected)
+               , complete_ok()
+               ])
+}
+
+/// the proxy forwarded exactly the body generated from `seed`
+fn downloaded_intact(seed: u64) -> Assertion {
+    all_of(vec![status_is(200), body_eq(payload(seed, BODY_LEN))])
+}
+
+lazy_static! {
+    pub static ref LARGE_REQUEST_LENGTH: Test =
+        Test::new( "Large Bodies 1"
+                 , "4 MiB request body with Content-Length"
+                 , upload("/bodies/request_length", 1, false)
+                 , expect(uploaded_intact(1),
+                          "Proxy must forward the whole request body \
+                           unchanged"))
+            .with_upstream("/bodies/request_length", |_: &Received| {
+                Script::respond(Response::new().body("ok"))
+            })
+            .with_segment_limit(SEGMENT_LIMIT)
+            .with_reference(7230, "3.3")
+            .with_tags(vec!["bodies"])
+            .with_explanation(
+                "A 4 MiB request body with a Content-Length is far larger \
+                 than any single read. The upstream must receive every byte \
+                 of it, in order. The bodies are pseudo-random, so any \
+                 corruption, truncation or reordering changes the checksum \
+                 the upstream records.");
+
+    pub static ref LARGE_REQUEST_CHUNKED: Test =
+        Test::new( "Large Bodies 2"
+                 , "4 MiB chunked request body"
+                 , upload("/bodies/request_chunked", 2, true)
+                 , expect(uploaded_intact(2),
+                          "Proxy must forward the whole request body \
+                           unchanged"))
+            .with_upstream("/bodies/request_chunked", |_: &Received| {
+                Script::respond(Response::new().body("ok"))
+            })
+            .with_segment_limit(SEGMENT_LIMIT)
+            .with_reference(7230, "4.1")
+            .with_tags(vec!["bodies", "framing"])
+            .with_explanation(
+                "A 4 MiB request body sent in 64 KiB chunks. The proxy may \
+                 forward it chunked or re-framed with a Content-Length (RFC \
+                 7230 §4.1.3), but the upstream must end up with exactly the \
+                 same bytes.");
+
+    pub static ref LARGE_RESPONSE_LENGTH: Test = {
+        let mut request = Request::new();
+        request.with_path("/bodies/response_length")
+               .with_header("Connection: close");
+        Test::new( "Large Bodies 3"
+                 , "4 MiB response body with Content-Length"
+                 , request
+                 , expect(downloaded_intact(3),
+                          "Proxy must forward the whole response body \
+                           unchanged"))
+            .with_upstream("/bodies/response_length", |_: &Received| {
+                Script::respond(Response::new().body(payload(3, BODY_LEN)))
+            })
+            .with_reference(7230, "3.3")
+            .with_tags(vec!["bodies"])
+            .with_explanation(
+                "A 4 MiB response body with a Content-Length. The client must \
+                 receive every byte of it, in order.")
+    };
+
+    pub static ref LARGE_RESPONSE_CHUNKED: Test = {
+        let mut request = Request::new();
+        request.with_path("/bodies/response_chunked")
+               .with_header("Connection: close");
+        Test::new( "Large Bodies 4"
+                 , "4 MiB chunked response body"
+                 , request
+                 , expect(downloaded_intact(4),
+                          "Proxy must forward the whole response body \
+                           unchanged"))
+            .with_upstream("/bodies/response_chunked", |_: &Received| {
+                Script::respond(Response::new()
+                    .header("Transfer-Encoding", "chunked")
+                    .body(chunked(&payload(4, BODY_LEN))))
+            })
+            .with_reference(7230, "4.1")
+            .with_tags(vec!["bodies", "framing"])
+            .with_explanation(
+                "A 4 MiB response body sent in 64 KiB chunks. However the \
+                 proxy frames it for the client, the decoded body must be \
+                 exactly what the upstream sent.")
+    };
+
+    pub static ref LARGE_RESPONSE_STREAMED: Test = {
+        let mut request = Request::new();
+        request.with_path("/bodies/response_streamed")
+               .with_header("Connection: close");
+        Test::new( "Large Bodies 5"
+                 , "4 MiB response body that the upstream pauses part-way \
+                    through"
+                 , request
+                 // the first megabyte was sent three seconds before the
+                 // rest, so a proxy that streams should have passed some
+                 // of it on well before then
+                 , expect(all_of(vec![ downloaded_intact(5)
+                                     , streamed(Duration::from_millis(2500))
+                                     ]),
+                          "Proxy should forward the whole response body \
+                           unchanged, streaming it rather than buffering \
+                           all of it"))
+            .with_upstream("/bodies/response_streamed", |_: &Received| {
+                let body = payload(5, BODY_LEN);
+                let (first, rest) = body.split_at(1 << 20);
+                Script::new()
+                    .send(Response::new()
+                            .header("Content-Length", BODY_LEN.to_string())
+                            .body(first))
+                    .delay(Duration::from_secs(3))
+                    .send(rest)
+            })
+            .with_level(Level::Should)
+            .with_tags(vec!["bodies", "streaming"])
+            .with_explanation(
+                "The upstream sends the first megabyte of a 4 MiB body, then \
+                 waits three seconds before sending the rest. The client \
+                 should start getting the body before the upstream finishes. \
+                 A proxy that buffers whole responses needs memory for every \
+                 response in flight, and makes every client wait for the last \
+                 byte.")
+    };
+}
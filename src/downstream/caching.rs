@@ -0,0 +1,255 @@
+//! Tests for how a caching proxy stores and reuses responses.
+//!
+//! A proxy doesn't have to cache anything, but if it does, RFC 7234
+//! limits what it may store and for how long. Each test requests the
+//! same resource more than once, and the upstream numbers its responses,
+//! so the test can tell whether a response came from the upstream or the
+//! proxy's cache. A proxy that doesn't cache passes every test, since
+//! forwarding every request is always allowed.
+use std::time::Duration;
+
+use upstream::{Received, Response, Script};
+
+use super::{Exchange, Request, Test};
+use super::expect::{ Assertion, all_of, any_of, body_eq, complete_ok, expect
+                   , header_matches, status_is, upstream_requests };
+
+/// Returns an upstream handler for a resource served with `headers`,
+/// whose body says how many times the upstream has served it.
+fn resource(headers: Vec<(&'static str, &'static str)>)
+            -> impl Fn(&Received) -> Script + Send + Sync + 'static {
+    move |request: &Received| {
+        let response = headers.iter()
+            .fold(Response::new(), |response, &(name, value)|
+                response.header(name, value));
+        Script::respond(response.body(format!("response {}",
+                                              request.attempt)))
+    }
+}
+
+/// the last response came from the upstream rather than the proxy's
+/// cache
+fn not_cached() -> Assertion {
+    all_of(vec![ upstream_requests("both requests",
+                     |requests: &[Received]| requests.len() >= 2)
+               , Assertion::new(|exchange: &Exchange| {
+                     let latest = format!( "response {}"
+                                         , exchange.upstream.len());
+                     body_eq(latest).check(exchange)
+                 })
+               , complete_ok()
+               ])
+}
+
+/// returns true if the first of the `Age` header values `ages` is above 0
+fn aged(ages: &[Vec<u8>]) -> bool {
+    ages.first()
+        .and_then(|age| String::from_utf8_lossy(age).trim()
+                            .parse::<u64>().ok())
+        .map(|age| age > 0)
+        .unwrap_or(false)
+}
+
+/// returns a `GET` request for `path`
+fn get(path: &str) -> Request {
+    let mut request = Request::new();
+    request.with_path(path)
+           .with_header("Connection: close");
+    request
+}
+
+lazy_static! {
+    pub static ref CACHE_NO_STORE: Test =
+        Test::new( "Caching 1"
+                 , "Response with `Cache-Control: no-store`"
+                 , get("/caching/no_store")
+                 , expect(not_cached(),
+                          "Proxy must not store a `no-store` response"))
+            .with_setup(get("/caching/no_store"))
+            .with_upstream("/caching/no_store", resource(vec![
+                ("Cache-Control", "no-store, max-age=60")
+              , ("ETag", "\"no-store\"")
+            ]))
+            .with_reference(7234, "5.2.2.3")
+            .with_tags(vec!["caching"])
+            .with_explanation(
+                "`Cache-Control: no-store` means no cache may keep any part \
+                 of the response (RFC 7234 §5.2.2.3), whatever else it says, \
+                 even `max-age=60`. The test requests the resource twice, and \
+                 the second request must reach the upstream.");
+
+    pub static ref CACHE_PRIVATE: Test =
+        Test::new( "Caching 2"
+                 , "Response with `Cache-Control: private`"
+                 , get("/caching/private")
+                 , expect(not_cached(),
+                          "A shared cache must not store a `private` response"))
+            .with_setup(get("/caching/private"))
+            .with_upstream("/caching/private", resource(vec![
+                ("Cache-Control", "private, max-age=60")
+              , ("Last-Modified", "Mon, 02 Jan 2017 00:00:00 GMT")
+            ]))
+            .with_reference(7234, "5.2.2.6")
+            .with_tags(vec!["caching", "security"])
+            .with_explanation(
+                "`Cache-Control: private` means the response is for a single \
+                 user, and a shared cache such as a proxy must not store it \
+                 (RFC 7234 §5.2.2.6). Serving it to another user leaks \
+                 whatever was personal about it.");
+
+    pub static ref CACHE_MAX_AGE: Test =
+        Test::new( "Caching 3"
+                 , "Response is requested again after its max-age"
+                 , get("/caching/max_age")
+                 , expect(not_cached(),
+                          "Proxy must not serve a response from its cache \
+                           after its max-age"))
+            .with_setup(get("/caching/max_age"))
+            .with_pause(Duration::from_secs(3))
+            .with_upstream("/caching/max_age", resource(vec![
+                ("Cache-Control", "max-age=1")
+            ]))
+            .with_reference(7234, "4.2")
+            .with_tags(vec!["caching"])
+            .with_explanation(
+                "The response has `max-age=1`, and the test asks for it again \
+                 three seconds later. By then it's stale, and a cache must \
+                 not serve a stale response without validating it first (RFC \
+                 7234 §4.2.4).");
+
+    pub static ref CACHE_S_MAXAGE: Test =
+        Test::new( "Caching 4"
+                 , "Response is requested again after its s-maxage, but \
+                    not its max-age"
+                 , get("/caching/s_maxage")
+                 , expect(not_cached(),
+                          "A shared cache must use s-maxage rather than \
+                           max-age"))
+            .with_setup(get("/caching/s_maxage"))
+            .with_pause(Duration::from_secs(3))
+            .with_upstream("/caching/s_maxage", resource(vec![
+                ("Cache-Control", "max-age=60, s-maxage=1")
+            ]))
+            .with_reference(7234, "5.2.2.9")
+            .with_tags(vec!["caching"])
+            .with_explanation(
+                "The response has `max-age=60, s-maxage=1`. In a shared \
+                 cache, `s-maxage` overrides `max-age` (RFC 7234 §5.2.2.9), \
+                 so after three seconds the proxy must treat the response as \
+                 stale, even though a private cache could keep using it.");
+
+    pub static ref CACHE_EXPIRED: Test =
+        Test::new( "Caching 5"
+                 , "Response with an `Expires` date in the past"
+                 , get("/caching/expired")
+                 , expect(not_cached(),
+                          "Proxy must not serve an expired response from its \
+                           cache"))
+            .with_setup(get("/caching/expired"))
+            .with_upstream("/caching/expired", resource(vec![
+                ("Expires", "Thu, 01 Jan 1970 00:00:00 GMT")
+              , ("Last-Modified", "Thu, 01 Jan 1970 00:00:00 GMT")
+            ]))
+            .with_reference(7234, "5.3")
+            .with_tags(vec!["caching"])
+            .with_explanation(
+                "The response's `Expires` date is in 1970, so it's stale the \
+                 moment it arrives (RFC 7234 §5.3). A cache must not serve it \
+                 again without going back to the upstream.");
+
+    pub static ref CACHE_AGE: Test =
+        Test::new( "Caching 6"
+                 , "Cached response is served with an `Age` header"
+                 , get("/caching/age")
+                 // only a response served from the cache needs an Age
+                 // header, and its age includes the pause
+                 , expect(all_of(vec![
+                              complete_ok()
+                            , any_of(vec![
+                                  upstream_requests("both requests",
+                                      |requests: &[Received]|
+                                          requests.len() >= 2)
+                                , header_matches("Age", "an Age above 0", aged)
+                              ])
+                          ]),
+                          "Proxy must send an Age header with responses \
+                           served from its cache, including the time they \
+                           spent there"))
+            .with_setup(get("/caching/age"))
+            .with_pause(Duration::from_secs(2))
+            .with_upstream("/caching/age", resource(vec![
+                ("Cache-Control", "max-age=60")
+              , ("ETag", "\"age\"")
+            ]))
+            .with_reference(7234, "5.1")
+            .with_tags(vec!["caching"])
+            .with_explanation(
+                "When a proxy serves a response from its cache, it must send \
+                 an `Age` header saying how long the response has been cached \
+                 (RFC 7234 §5.1, §4). That age has to include the time it \
+                 spent in the cache, or clients further down will keep it \
+                 longer than the upstream allowed.");
+
+    pub static ref CACHE_VARY: Test = {
+        let mut english = get("/caching/vary");
+        english.with_header("Accept-Language: en");
+        let mut french = get("/caching/vary");
+        french.with_header("Accept-Language: fr");
+        Test::new( "Caching 7"
+                 , "Response that varies on `Accept-Language`"
+                 , french
+                 , expect(all_of(vec![status_is(200), body_eq("fr")]),
+                          "Proxy must not serve a cached response to a \
+                           request with different values of the headers it \
+                           varies on"))
+            .with_setup(english)
+            .with_upstream("/caching/vary", |request: &Received| {
+                let language = request.header("Accept-Language")
+                                      .unwrap_or(&b"none"[..])
+                                      .to_vec();
+                Script::respond(Response::new()
+                    .header("Cache-Control", "max-age=60")
+                    .header("Vary", "Accept-Language")
+                    .body(language))
+            })
+            .with_reference(7234, "4.1")
+            .with_tags(vec!["caching", "security"])
+            .with_explanation(
+                "The response has `Vary: Accept-Language`, so a cached copy \
+                 can only be used for a request with the same \
+                 `Accept-Language` (RFC 7234 §4.1). A cache that ignores Vary \
+                 serves the French page to the English speaker. Worse, it can \
+                 serve a response meant for one kind of client to every \
+                 client.")
+    };
+
+    pub static ref CACHE_AUTHORIZATION: Test = {
+        let mut alice = get("/caching/authorization");
+        alice.with_header("Authorization: Bearer alice");
+        let mut bob = get("/caching/authorization");
+        bob.with_header("Authorization: Bearer bob");
+        Test::new( "Caching 8"
+                 , "Response to a request with `Authorization`"
+                 , bob
+                 , expect(all_of(vec![status_is(200), body_eq("Bearer bob")]),
+                          "A shared cache must not serve a response to an \
+                           authorized request to anyone else"))
+            .with_setup(alice)
+            .with_upstream("/caching/authorization", |request: &Received| {
+                let user = request.header("Authorization")
+                                  .unwrap_or(&b"nobody"[..])
+                                  .to_vec();
+                Script::respond(Response::new()
+                    .header("Cache-Control", "max-age=60")
+                    .body(user))
+            })
+            .with_reference(7234, "3.2")
+            .with_tags(vec!["caching", "security"])
+            .with_explanation(
+                "The first request has `Authorization: Bearer alice`, and the \
+                 response doesn't say it may be shared. RFC 7234 §3.2 forbids \
+                 a shared cache from using a response to an authorized \
+                 request for any other request. Bob must get his own \
+                 response, not Alice's.")
+    };
+}
@@ -0,0 +1,49 @@
+//! Checks shared between test suites.
+use std::io::Result;
+
+use super::{Exchange, Outcome, Status};
+use super::expect::{any_of, expect, incomplete, observed_status, status_is};
+
+/// Returns a check that the proxy either rejected a truncated upstream
+/// response, or passed it on in a way that lets the client tell it was
+/// truncated.
+pub fn truncation_visible() -> impl Fn(&Exchange) -> Result<Status> + Sync {
+    expect( any_of(vec![status_is(502), incomplete()])
+          , "Proxy must not present a truncated response as complete")
+}
+
+/// every outcome in `outcomes`, joined with "or"
+fn either(outcomes: &[Outcome]) -> String {
+    outcomes.iter()
+        .map(|outcome| outcome.to_string())
+        .collect::<Vec<_>>()
+        .join(" or ")
+}
+
+/// what the proxy did in `exchange`, in the same terms as `Outcome`
+fn observed(exchange: &Exchange) -> String {
+    let mut observed = observed_status(exchange);
+    if !exchange.upstream.is_empty() {
+        observed.push_str(", forwarded to the upstream");
+    }
+    observed
+}
+
+/// check that the proxy did one of the things in `acceptable`, and
+/// report which
+pub fn one_of(exchange: &Exchange, acceptable: &[Outcome], why: &'static str)
+              -> Result<Status> {
+    let accepted = acceptable.iter()
+        .find(|outcome| outcome.observed_in(exchange));
+    let status = match accepted {
+        Some(&outcome) => Status::Accepted(outcome)
+      , None => Status::Unexpected {
+            why: why
+          , expected: either(acceptable)
+          , actual: observed(exchange)
+          , bytes: exchange.response.clone()
+        }
+    };
+
+    Ok(status)
+}
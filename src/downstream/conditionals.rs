@@ -0,0 +1,300 @@
+//! Tests for conditional requests.
+//!
+//! A proxy that doesn't cache must forward `If-None-Match`,
+//! `If-Modified-Since`, `If-Match` and `If-Range` untouched, and pass on
+//! the upstream's 304 Not Modified or 412 Precondition Failed as it is. A
+//! caching proxy may answer conditional requests from its cache, but only
+//! with the result the upstream would have given (RFC 7232 §6, RFC 7234
+//! §4.3.2), and it must keep entity-tags exactly as the upstream sent
+//! them: a weak tag (`W/"..."`) only promises the representation is
+//! equivalent, so turning it into a strong one would let clients splice
+//! together ranges of different representations.
+use upstream::{Received, Response, Script};
+
+use super::{Request, Test, Verb};
+use super::expect::{ Assertion, all_of, body_eq, complete_ok, expect
+                   , header_eq, status_is, upstream_header };
+
+const BODY: &'static str = "hello world";
+const LAST_MODIFIED: &'static str = "Mon, 02 Jan 2017 00:00:00 GMT";
+
+/// returns true if the entity-tags `a` and `b` match, using the weak
+/// comparison function if `weak` is true (RFC 7232 §2.3.2)
+fn etags_match(a: &str, b: &str, weak: bool) -> bool {
+    let is_weak = |tag: &str| tag.starts_with("W/");
+    let opaque = |tag: &str| tag.trim_left_matches("W/").to_owned();
+    if weak {
+        opaque(a) == opaque(b)
+    } else {
+        !is_weak(a) && !is_weak(b) && a == b
+    }
+}
+
+/// returns true if any tag in the list `header` matches `etag`
+fn any_match(header: &[u8], etag: &str, weak: bool) -> bool {
+    let header = String::from_utf8_lossy(header);
+    header.split(',')
+          .map(|tag| tag.trim())
+          .any(|tag| tag == "*" || etags_match(tag, etag, weak))
+}
+
+/// Returns an upstream handler for a resource with the entity-tag `etag`,
+/// which evaluates the preconditions in each request as RFC 7232 §6
+/// describes.
+fn resource(etag: &'static str, cache_control: &'static str)
+            -> impl Fn(&Received) -> Script + Send + Sync + 'static {
+    move |request: &Received| {
+        let response = Response::new()
+            .header("ETag", etag)
+            .header("Last-Modified", LAST_MODIFIED)
+            .header("Cache-Control", cache_control);
+
+        if let Some(tags) = request.header("If-Match") {
+            if !any_match(tags, etag, false) {
+                return Script::respond(response
+                    .status_code(412, "Precondition Failed"))
+            }
+        }
+        let not_modified = match request.header("If-None-Match") {
+            Some(tags) => any_match(tags, etag, true)
+          , None => request.header("If-Modified-Since")
+                           .map(|date| date == LAST_MODIFIED.as_bytes())
+                           .unwrap_or(false)
+        };
+        if not_modified && (request.method == b"GET" ||
+                            request.method == b"HEAD") {
+            return Script::respond(response
+                .status_code(304, "Not Modified"))
+        }
+
+        let range_applies = match request.header("If-Range") {
+            Some(tag) => etags_match(&String::from_utf8_lossy(tag), etag,
+                                     false)
+          , None => true
+        };
+        match request.header("Range") {
+            Some(b"bytes=0-4") if range_applies =>
+                Script::respond(response
+                    .status_code(206, "Partial Content")
+                    .header("Content-Range",
+                            format!("bytes 0-4/{}", BODY.len()))
+                    .body(&BODY[..5]))
+          , _ => Script::respond(response.body(BODY))
+        }
+    }
+}
+
+/// if the upstream recieved the request, its headers named `name` were
+/// exactly `value`, and the proxy responded with `code`
+fn forwarded_unchanged(name: &'static str, value: &'static str, code: u16)
+                       -> Assertion {
+    all_of(vec![ upstream_header(name, "the condition unchanged",
+                     move |values: &[Vec<u8>]|
+                         values.iter().all(|v| v == value.as_bytes()))
+               , status_is(code)
+               ])
+}
+
+/// returns a `GET` request for `path` with the header line `condition`
+fn conditional(path: &str, condition: &str) -> Request {
+    let mut request = Request::new();
+    request.with_path(path)
+           .with_header(condition)
+           .with_header("Connection: close");
+    request
+}
+
+lazy_static! {
+    pub static ref IF_NONE_MATCH_FORWARDED: Test =
+        Test::new( "Conditionals 1"
+                 , "`If-None-Match` that matches the upstream's entity-tag"
+                 , conditional( "/conditionals/if_none_match"
+                              , "If-None-Match: \"other\", \"v1\"")
+                 , expect(forwarded_unchanged( "If-None-Match"
+                                             , "\"other\", \"v1\"", 304),
+                          "Proxy must forward If-None-Match unchanged, and \
+                           pass on the upstream's 304 Not Modified"))
+            .with_upstream("/conditionals/if_none_match",
+                           resource("\"v1\"", "no-cache"))
+            .with_reference(7232, "3.2")
+            .with_tags(vec!["conditionals"])
+            .with_explanation(
+                "`If-None-Match` lists entity-tags the client already has. \
+                 The proxy must forward it unchanged. When the upstream finds \
+                 a match and answers 304 Not Modified, the proxy must pass \
+                 that on (RFC 7232 §3.2), not turn it into a full response.");
+
+    pub static ref IF_MODIFIED_SINCE_FORWARDED: Test =
+        Test::new( "Conditionals 2"
+                 , "`If-Modified-Since` the upstream's Last-Modified date"
+                 , conditional( "/conditionals/if_modified_since"
+                              , &format!("If-Modified-Since: {}",
+                                         LAST_MODIFIED))
+                 , expect(forwarded_unchanged( "If-Modified-Since"
+                                             , LAST_MODIFIED, 304),
+                          "Proxy must forward If-Modified-Since unchanged, \
+                           and pass on the upstream's 304 Not Modified"))
+            .with_upstream("/conditionals/if_modified_since",
+                           resource("\"v1\"", "no-cache"))
+            .with_reference(7232, "3.3")
+            .with_tags(vec!["conditionals"])
+            .with_explanation(
+                "`If-Modified-Since` carries the date of the client's copy. \
+                 The proxy must forward it byte-for-byte, because dates are \
+                 compared exactly, and pass on the upstream's 304 Not \
+                 Modified (RFC 7232 §3.3).");
+
+    pub static ref IF_MATCH_FORWARDED: Test = {
+        let mut request = conditional( "/conditionals/if_match"
+                                     , "If-Match: \"v2\"");
+        request.with_verb(Verb::Put)
+               .with_header("Content-Length: 5")
+               .with_body("hello");
+        Test::new( "Conditionals 3"
+                 , "`If-Match` that doesn't match the upstream's entity-tag"
+                 , request
+                 , expect(forwarded_unchanged("If-Match", "\"v2\"", 412),
+                          "Proxy must forward If-Match unchanged, and pass on \
+                           the upstream's 412 Precondition Failed"))
+            .with_upstream("/conditionals/if_match",
+                           resource("\"v1\"", "no-cache"))
+            .with_reference(7232, "3.1")
+            .with_tags(vec!["conditionals"])
+            .with_explanation(
+                "`If-Match` makes a `PUT` conditional on the resource still \
+                 having the entity-tag the client saw. The upstream's tag \
+                 doesn't match, so it answers 412 Precondition Failed, and \
+                 the proxy must pass that on (RFC 7232 §3.1). Dropping the \
+                 header would let the client overwrite someone else's \
+                 changes.")
+    };
+
+    pub static ref IF_RANGE_FORWARDED: Test = {
+        let mut request = conditional( "/conditionals/if_range"
+                                     , "If-Range: \"v1\"");
+        request.with_header("Range: bytes=0-4");
+        Test::new( "Conditionals 4"
+                 , "`If-Range` that matches the upstream's entity-tag"
+                 , request
+                 , expect(forwarded_unchanged("If-Range", "\"v1\"", 206),
+                          "Proxy must forward If-Range unchanged, and pass on \
+                           the upstream's 206 Partial Content"))
+            .with_upstream("/conditionals/if_range",
+                           resource("\"v1\"", "no-cache"))
+            .with_reference(7233, "3.2")
+            .with_tags(vec!["conditionals", "ranges"])
+            .with_explanation(
+                "`If-Range` asks for the range only if the representation \
+                 still has the given entity-tag. The proxy must forward it \
+                 with the Range, so the upstream can answer 206 Partial \
+                 Content when the tag matches (RFC 7233 §3.2).")
+    };
+
+    pub static ref NOT_MODIFIED_FROM_CACHE: Test =
+        Test::new( "Conditionals 5"
+                 , "`If-None-Match` for a cached response"
+                 , conditional( "/conditionals/cached_304"
+                              , "If-None-Match: \"v1\"")
+                 // a 200 with no body is how a 304 gets mangled by a proxy
+                 // that forgets 304s can't have one
+                 , expect(all_of(vec![ status_is(304)
+                                     , header_eq("ETag", "\"v1\"")
+                                     ]),
+                          "Proxy must answer with 304 Not Modified and the \
+                           cached response's entity-tag"))
+            .with_setup(conditional("/conditionals/cached_304",
+                                    "X-Flossy: prime the cache"))
+            .with_upstream("/conditionals/cached_304",
+                           resource("\"v1\"", "max-age=60"))
+            .with_reference(7234, "4.3.2")
+            .with_tags(vec!["conditionals", "caching"])
+            .with_explanation(
+                "A caching proxy may answer an `If-None-Match` request \
+                 itself, but only with what the upstream would have said: 304 \
+                 Not Modified, with the cached response's entity-tag (RFC \
+                 7234 §4.3.2). A 304 can't have a body, and a 200 with no \
+                 body is not the same thing.");
+
+    pub static ref MODIFIED_FROM_CACHE: Test =
+        Test::new( "Conditionals 6"
+                 , "`If-None-Match` with a stale entity-tag for a cached \
+                    response"
+                 , conditional( "/conditionals/cached_200"
+                              , "If-None-Match: \"v0\"")
+                 , expect(all_of(vec![status_is(200), body_eq(BODY)]),
+                          "Proxy must send the whole representation when the \
+                           entity-tag doesn't match"))
+            .with_setup(conditional("/conditionals/cached_200",
+                                    "X-Flossy: prime the cache"))
+            .with_upstream("/conditionals/cached_200",
+                           resource("\"v1\"", "max-age=60"))
+            .with_reference(7234, "4.3.2")
+            .with_tags(vec!["conditionals", "caching"])
+            .with_explanation(
+                "The client's entity-tag doesn't match the cached response. A \
+                 caching proxy must send the whole representation, not a 304 \
+                 Not Modified (RFC 7234 §4.3.2).");
+
+    pub static ref WEAK_ETAG_PRESERVED: Test =
+        Test::new( "Conditionals 7"
+                 , "Response with a weak entity-tag"
+                 , conditional( "/conditionals/weak_etag"
+                              , "X-Flossy: no precondition")
+                 , expect(all_of(vec![ complete_ok()
+                                     , header_eq("ETag", "W/\"v1\"")
+                                     ]),
+                          "Proxy must not change the upstream's entity-tag"))
+            .with_upstream("/conditionals/weak_etag",
+                           resource("W/\"v1\"", "max-age=60"))
+            .with_reference(7232, "2.3")
+            .with_tags(vec!["conditionals"])
+            .with_explanation(
+                "A weak entity-tag (`W/\"v1\"`) only promises that \
+                 representations are equivalent, not byte-for-byte identical \
+                 (RFC 7232 §2.3). If the proxy strips the `W/`, clients may \
+                 combine ranges from different representations into a corrupt \
+                 whole. The proxy must forward the tag exactly as the \
+                 upstream sent it.");
+
+    pub static ref WEAK_IF_NONE_MATCH: Test =
+        Test::new( "Conditionals 8"
+                 , "`If-None-Match` with a weak entity-tag for a cached \
+                    response"
+                 , conditional( "/conditionals/weak_if_none_match"
+                              , "If-None-Match: W/\"v1\"")
+                 , expect(all_of(vec![ status_is(304)
+                                     , header_eq("ETag", "W/\"v1\"")
+                                     ]),
+                          "Proxy must answer with 304 Not Modified and the \
+                           cached response's entity-tag"))
+            .with_setup(conditional("/conditionals/weak_if_none_match",
+                                    "X-Flossy: prime the cache"))
+            .with_upstream("/conditionals/weak_if_none_match",
+                           resource("W/\"v1\"", "max-age=60"))
+            .with_reference(7232, "3.2")
+            .with_tags(vec!["conditionals", "caching"])
+            .with_explanation(
+                "`If-None-Match` uses the weak comparison function (RFC 7232 \
+                 §3.2), so `W/\"v1\"` matches a cached response with the same \
+                 weak tag. A caching proxy answering the request itself must \
+                 say 304 Not Modified, and keep the tag weak.");
+
+    pub static ref STRONG_ETAG_PRESERVED: Test =
+        Test::new( "Conditionals 9"
+                 , "Response with a strong entity-tag"
+                 , conditional( "/conditionals/strong_etag"
+                              , "X-Flossy: no precondition")
+                 , expect(all_of(vec![ complete_ok()
+                                     , header_eq("ETag", "\"v1\"")
+                                     ]),
+                          "Proxy must not change the upstream's entity-tag"))
+            .with_upstream("/conditionals/strong_etag",
+                           resource("\"v1\"", "max-age=60"))
+            .with_reference(7232, "2.3")
+            .with_tags(vec!["conditionals"])
+            .with_explanation(
+                "A strong entity-tag must reach the client exactly as the \
+                 upstream sent it. A proxy that weakens it, as some do when \
+                 they compress responses, takes away the client's ability to \
+                 make range requests and `If-Match` updates against it.");
+}
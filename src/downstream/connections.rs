@@ -0,0 +1,148 @@
+//! Tests for how the proxy reuses connections to the upstream.
+//!
+//! Proxies usually keep a pool of upstream connections, but a connection
+//! can only be reused if the upstream hasn't closed it, the previous
+//! response didn't ask for it to be closed (RFC 7230 §6.6), and nothing
+//! was left unread on it. Each test sends a setup request, and then
+//! checks which upstream connection the test's own request arrived on.
+use std::time::Duration;
+
+use upstream::{Received, Response, Script};
+
+use super::{Exchange, Request, Test};
+use super::expect::{ Assertion, Mismatch, all_of, body_eq, expect, status_is
+                   , upstream_requests };
+
+/// the test's request reached the upstream on a different connection
+/// from the setup request, and the proxy forwarded the upstream's
+/// response to it
+fn not_reused() -> Assertion {
+    all_of(vec![new_connection(), status_is(200), body_eq("fresh")])
+}
+
+/// the test's request reached the upstream on a different connection
+/// from the setup request
+fn new_connection() -> Assertion {
+    Assertion::new(|exchange: &Exchange| {
+        let attempts = &exchange.upstream;
+        let actual = match attempts.len() {
+            0 => "nothing reached the upstream"
+          , 1 => "only the setup request reached the upstream"
+          , _ if attempts[1].connection != attempts[0].connection =>
+                return None
+          , _ => "both requests on the same connection"
+        };
+        Some(Mismatch { expected: "the request on a new connection".to_owned()
+                      , actual: actual.to_owned()
+                      })
+    })
+}
+
+lazy_static! {
+    pub static ref REUSE_AFTER_CONNECTION_CLOSE: Test = {
+        let mut request = Request::new();
+        request.with_path("/connections/close")
+               .with_header("Connection: close");
+        Test::new( "Connection Reuse 1"
+                 , "Upstream responds with `Connection: close`"
+                 , request.clone()
+                 , expect(not_reused(),
+                          "Proxy must not reuse a connection after the \
+                           upstream sent `Connection: close`"))
+            .with_setup(request)
+            .with_upstream("/connections/close", |request: &Received| {
+                // leave the connection open, so we can see if the proxy
+                // sends another request on it
+                if request.attempt == 1 {
+                    Script::respond(Response::new()
+                        .header("Connection", "close")
+                        .body("first"))
+                } else {
+                    Script::respond(Response::new().body("fresh"))
+                }
+            })
+            .with_reference(7230, "6.6")
+            .with_tags(vec!["connections"])
+            .with_explanation(
+                "The upstream ends a response with `Connection: close`. After \
+                 that, RFC 7230 §6.6 says the recipient must not send any \
+                 more requests on that connection. The upstream in this test \
+                 leaves the connection open anyway, so a proxy that reuses it \
+                 is caught doing so.")
+    };
+
+    pub static ref REUSE_AFTER_EXTRA_BYTES: Test = {
+        let mut request = Request::new();
+        request.with_path("/connections/extra_bytes")
+               .with_header("Connection: close");
+        Test::new( "Connection Reuse 2"
+                 , "Upstream sends extra bytes after a response"
+                 , request.clone()
+                 , expect(not_reused(),
+                          "Proxy must not reuse a connection with unread \
+                           bytes after the last response"))
+            .with_setup(request)
+            .with_upstream("/connections/extra_bytes", |request: &Received| {
+                if request.attempt == 1 {
+                    Script::new()
+                        .send(Response::new().body("first"))
+                        .send("HTTP/1.1 200 OK\r\n\
+                               Content-Length: 5\r\n\
+                               \r\n\
+                               stale")
+                } else {
+                    Script::respond(Response::new().body("fresh"))
+                }
+            })
+            .with_reference(7230, "6.3")
+            .with_tags(vec!["connections", "security"])
+            .with_explanation(
+                "The upstream sends a second, unrequested response right \
+                 after the first. A proxy that puts the connection back in \
+                 its pool will read those bytes as the response to whatever \
+                 request it sends next. That request may belong to a \
+                 different client, so the client gets someone else's \
+                 response, or one an attacker planted. A connection with \
+                 unread bytes on it can't be reused (RFC 7230 §6.3).")
+    };
+
+    pub static ref REUSE_AFTER_IDLE_CLOSE: Test = {
+        let mut request = Request::new();
+        request.with_path("/connections/idle_close")
+               .with_header("Connection: close");
+        Test::new( "Connection Reuse 3"
+                 , "Upstream closes idle connections"
+                 , request.clone()
+                 // the closed connection can't have recieved the request,
+                 // so the proxy just needs to get the request through on
+                 // another one
+                 , expect(all_of(vec![
+                              upstream_requests("the request after the setup",
+                                  |requests: &[Received]| requests.len() >= 2)
+                            , status_is(200)
+                            , body_eq("fresh")
+                          ]),
+                          "Proxy must not send requests on a connection the \
+                           upstream closed"))
+            .with_setup(request)
+            .with_pause(Duration::from_secs(2))
+            .with_upstream("/connections/idle_close", |request: &Received| {
+                if request.attempt == 1 {
+                    Script::new()
+                        .send(Response::new().body("first"))
+                        .delay(Duration::from_secs(1))
+                        .close()
+                } else {
+                    Script::respond(Response::new().body("fresh"))
+                }
+            })
+            .with_reference(7230, "6.3.1")
+            .with_tags(vec!["connections", "retries"])
+            .with_explanation(
+                "The upstream closes a pooled connection while it's idle. The \
+                 proxy's next request has to get through anyway, on a new \
+                 connection. If the proxy sent it on the closed connection \
+                 first, it can retry, because the upstream can't have \
+                 received it (RFC 7230 §6.3.1).")
+    };
+}
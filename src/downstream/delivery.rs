@@ -0,0 +1,237 @@
+//! Strategies for delivering a request to the proxy in pieces.
+//!
+//! A proxy whose parser only works when a whole request arrives in one
+//! read will pass every test if we always send requests in a single
+//! write. Splitting requests up (and optionally pausing between the
+//! pieces) exercises the proxy's parser state machine at every boundary
+//! we choose.
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Where to split a request into separately-written segments.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Split {
+    /// send the whole request in a single write
+    Whole
+  , /// send every byte in its own write
+    Bytewise
+  , /// split at each of these byte offsets
+    At(Vec<usize>)
+  , /// split `offset` bytes into every occurrence of `needle`
+    Inside { needle: Vec<u8>, offset: usize }
+  , /// split a chunked body after every chunk-size line, between the
+    /// size and the chunk's data
+    Chunks
+}
+
+/// What the client does with its connection after sending a request.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Finish {
+    /// keep the connection open, and read the response
+    Open
+  , /// shut down the write half of the connection, and read the response
+    HalfClose
+  , /// reset the connection without reading the response
+    Reset
+}
+
+impl Default for Finish {
+    #[inline] fn default() -> Self { Finish::Open }
+}
+
+/// How a request is written to the proxy.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Delivery {
+    pub split: Split
+  , /// how long to wait before writing each segment after the first
+    pub delay: Option<Duration>
+  , /// the most segments to write; whatever is left of the request
+    /// after the last but one goes in the last
+    pub limit: Option<usize>
+}
+
+impl Delivery {
+    #[inline] pub fn whole() -> Self {
+        Delivery { split: Split::Whole, delay: None, limit: None }
+    }
+
+    #[inline] pub fn bytewise() -> Self {
+        Delivery { split: Split::Bytewise, delay: None, limit: None }
+    }
+
+    #[inline] pub fn at(offsets: Vec<usize>) -> Self {
+        Delivery { split: Split::At(offsets), delay: None, limit: None }
+    }
+
+    #[inline] pub fn inside<N>(needle: N, offset: usize) -> Self
+    where N: Into<Vec<u8>> {
+        Delivery { split: Split::Inside { needle: needle.into()
+                                        , offset: offset }
+                 , delay: None
+                 , limit: None }
+    }
+
+    /// split between the `CR` and `LF` of every line ending
+    #[inline] pub fn mid_crlf() -> Self { Delivery::inside("\r\n", 1) }
+
+    /// split after the first byte of every header name
+    #[inline] pub fn mid_header_name() -> Self {
+        Delivery::inside("\r\n", 3)
+    }
+
+    /// split between the size and the data of every chunk
+    #[inline] pub fn chunks() -> Self {
+        Delivery { split: Split::Chunks, delay: None, limit: None }
+    }
+
+    /// the named strategies that `--delivery all` runs tests under
+    pub fn all() -> Vec<Self> {
+        vec![ Delivery::whole()
+            , Delivery::bytewise()
+            , Delivery::mid_crlf()
+            , Delivery::mid_header_name()
+            , Delivery::chunks()
+            ]
+    }
+
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay); self
+    }
+
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit); self
+    }
+
+    /// Split `request` into the segments to write.
+    pub fn segments(&self, request: &[u8]) -> Vec<Vec<u8>> {
+        let mut cuts = match self.split {
+            Split::Whole => vec![]
+          , Split::Bytewise => (1..request.len()).collect()
+          , Split::At(ref offsets) => offsets.clone()
+          , Split::Inside { ref needle, offset } if !needle.is_empty() =>
+                request.windows(needle.len())
+                       .enumerate()
+                       .filter(|&(_, window)| window == &needle[..])
+                       .map(|(i, _)| i + offset)
+                       .collect()
+          , Split::Inside { .. } => vec![]
+          , Split::Chunks => chunk_starts(request)
+        };
+        cuts.retain(|&cut| cut > 0 && cut < request.len());
+        cuts.sort();
+        cuts.dedup();
+        if let Some(limit) = self.limit {
+            cuts.truncate(limit.saturating_sub(1));
+        }
+
+        let mut segments = Vec::with_capacity(cuts.len() + 1);
+        let mut start = 0;
+        for cut in cuts {
+            segments.push(request[start..cut].to_vec());
+            start = cut;
+        }
+        segments.push(request[start..].to_vec());
+        segments
+    }
+}
+
+/// the offset of the first occurrence of `needle` in `haystack`
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// the offsets in `request` just after each chunk-size line of its body,
+/// where the data of each chunk starts. A body that isn't chunked has
+/// none.
+fn chunk_starts(request: &[u8]) -> Vec<usize> {
+    let mut starts = Vec::new();
+    let mut pos = match find(request, b"\r\n\r\n") {
+        Some(end) => end + 4
+      , None => return starts
+    };
+    while let Some(eol) = find(&request[pos..], b"\r\n") {
+        let line = String::from_utf8_lossy(&request[pos..pos + eol]);
+        let size = line.split(';').next().unwrap_or("").trim();
+        let size = match usize::from_str_radix(size, 16) {
+            Ok(size) => size
+          , Err(_) => break
+        };
+        pos += eol + 2;
+        starts.push(pos);
+        // skip the chunk's data and the CRLF after it
+        pos += size + 2;
+        if size == 0 || pos > request.len() { break }
+    }
+    starts
+}
+
+impl Default for Delivery {
+    #[inline] fn default() -> Self { Delivery::whole() }
+}
+
+impl fmt::Display for Delivery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.split == Delivery::mid_crlf().split {
+            write!(f, "crlf")?;
+        } else if self.split == Delivery::mid_header_name().split {
+            write!(f, "names")?;
+        } else if self.split == Split::Chunks {
+            write!(f, "chunks")?;
+        } else {
+            match self.split {
+                Split::Whole => write!(f, "whole")?
+              , Split::Bytewise => write!(f, "bytewise")?
+              , Split::At(ref offsets) => {
+                    let offsets = offsets.iter()
+                        .map(|o| o.to_string())
+                        .collect::<Vec<_>>();
+                    write!(f, "{}", offsets.join(","))?
+                }
+              , Split::Inside { ref needle, offset } =>
+                    write!( f, "{} bytes into {:?}"
+                          , offset, String::from_utf8_lossy(needle))?
+              , Split::Chunks => write!(f, "chunks")?
+            }
+        }
+        if let Some(delay) = self.delay {
+            let millis = delay.as_secs() * 1000
+                       + (delay.subsec_nanos() / 1_000_000) as u64;
+            write!(f, "@{}", millis)?;
+        }
+        if let Some(limit) = self.limit {
+            write!(f, " in at most {} writes", limit)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses strategies as given on the command line: `whole`, `bytewise`,
+/// `crlf`, `names`, `chunks`, or a comma-separated list of offsets, optionally
+/// followed by `@` and a delay in milliseconds, e.g. `bytewise@10`.
+impl FromStr for Delivery {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '@');
+        let split = parts.next().unwrap_or("");
+        let mut delivery = match split {
+            "whole" => Delivery::whole()
+          , "bytewise" => Delivery::bytewise()
+          , "crlf" => Delivery::mid_crlf()
+          , "names" => Delivery::mid_header_name()
+          , "chunks" => Delivery::chunks()
+          , offsets => offsets.split(',')
+                .map(|o| o.trim().parse::<usize>())
+                .collect::<Result<Vec<_>, _>>()
+                .map(Delivery::at)
+                .map_err(|_| format!("unknown delivery strategy {:?}", s))?
+        };
+        if let Some(millis) = parts.next() {
+            let millis = millis.parse::<u64>()
+                .map_err(|_| format!("invalid delay in {:?}", s))?;
+            delivery.delay = Some(Duration::from_millis(millis));
+        }
+        Ok(delivery)
+    }
+}
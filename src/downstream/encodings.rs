@@ -0,0 +1,318 @@
+//! Tests for whether the proxy leaves content-codings alone.
+//!
+//! A `Content-Encoding` is part of the representation, not the message,
+//! so a proxy has no business decoding or re-encoding a body unless it's
+//! been set up to, and never when the response says
+//! `Cache-Control: no-transform` (RFC 7230 §5.7.2). A proxy that does
+//! decode a body has to drop the `Content-Encoding` and fix up the
+//! `Content-Length` to match, and shouldn't send a client a coding it
+//! didn't accept (RFC 7231 §5.3.4). Responses that can't have a body,
+//! to `HEAD` or with 204 No Content, can still have a `Content-Encoding`,
+//! and the proxy mustn't try to give them one.
+//!
+//! The upstream encodes its bodies with uncompressed blocks, so any
+//! proxy that re-encodes a body will change its bytes.
+use upstream::{self, Received, Response, Script};
+
+use super::{Exchange, Level, Request, Test, Verb};
+use super::expect::{ Assertion, Mismatch, all_of, any_of, body_eq, complete_ok
+                   , expect, header_absent, header_eq, no_body, status_is };
+
+/// long enough to need more than one block in every coding
+const LEN: usize = 100_000;
+
+/// the representation's bytes before any content-coding
+fn identity() -> Vec<u8> {
+    upstream::payload(43, LEN)
+}
+
+/// `identity()` with each content-coding in the list `codings` applied,
+/// in order
+fn encoded(codings: &str) -> Vec<u8> {
+    codings.split(',').map(|coding| coding.trim())
+        .fold(identity(), |body, coding| match coding {
+            "gzip" => upstream::gzip(&body)
+          , "deflate" => upstream::deflate(&body)
+          , "br" => upstream::brotli(&body)
+          , _ => body
+        })
+}
+
+/// Returns an upstream handler that always sends the representation
+/// encoded with `codings`, whatever the request accepts.
+fn resource(codings: &'static str, cache_control: &'static str)
+            -> impl Fn(&Received) -> Script + Send + Sync + 'static {
+    let body = encoded(codings);
+    move |_: &Received| Script::respond(Response::new()
+        .header("Content-Type", "application/octet-stream")
+        .header("Content-Encoding", codings)
+        .header("Cache-Control", cache_control)
+        .header("Vary", "Accept-Encoding")
+        .body(body.clone()))
+}
+
+/// if the response has a `Content-Length`, it's the length of everything
+/// after the head
+fn length_consistent() -> Assertion {
+    Assertion::new(|exchange: &Exchange| {
+        let length = exchange.header("Content-Length")
+            .and_then(|length| String::from_utf8_lossy(&length).trim()
+                                   .parse::<usize>().ok());
+        match (length, exchange.head_len()) {
+            (Some(length), Some(head_len))
+                if exchange.header("Transfer-Encoding").is_none() &&
+                   exchange.response.len() - head_len != length =>
+                    Some(Mismatch {
+                        expected: format!( "Content-Length: {}"
+                                         , exchange.response.len() - head_len)
+                      , actual: format!("Content-Length: {}", length)
+                    })
+          , _ => None
+        }
+    })
+}
+
+/// the proxy forwarded a body encoded with `codings` exactly as the
+/// upstream sent it, or, if `may_decode`, sent the decoded body with no
+/// `Content-Encoding` instead
+fn transparent(codings: &'static str, may_decode: bool) -> Assertion {
+    let forwarded = all_of(vec![ header_eq("Content-Encoding", codings)
+                               , body_eq(encoded(codings))
+                               ]);
+    let body = if may_decode {
+        any_of(vec![ forwarded
+                   , all_of(vec![ header_absent("Content-Encoding")
+                                , body_eq(identity())
+                                ])
+                   ])
+    } else {
+        forwarded
+    };
+    all_of(vec![complete_ok(), body, length_consistent()])
+}
+
+/// the `Content-Length` of a response to `HEAD`, if it has one, is the
+/// length of the body a `GET` would have got, in whichever coding the
+/// proxy would have sent it
+fn head_length() -> Assertion {
+    Assertion::new(|exchange: &Exchange| {
+        let expected = match exchange.header("Content-Encoding") {
+            Some(_) => encoded("gzip").len()
+          , None => LEN
+        };
+        let length = exchange.header("Content-Length")
+            .map(|length| String::from_utf8_lossy(&length).trim().to_owned());
+        match length {
+            Some(ref length) if *length != expected.to_string() =>
+                Some(Mismatch { expected: format!( "Content-Length: {}"
+                                                 , expected)
+                              , actual: format!("Content-Length: {}", length)
+                              })
+          , _ => None
+        }
+    })
+}
+
+/// returns a `GET` request for `path` that accepts the codings `accept`
+fn get(path: &str, accept: &str) -> Request {
+    let mut request = Request::new();
+    request.with_path(path)
+           .with_header(format!("Accept-Encoding: {}", accept))
+           .with_header("Connection: close");
+    request
+}
+
+lazy_static! {
+    pub static ref GZIP_BODY: Test =
+        Test::new( "Encoding 1"
+                 , "Response body with `Content-Encoding: gzip`"
+                 , get("/encodings/gzip", "gzip")
+                 , expect(transparent("gzip", false),
+                          "Proxy must forward an encoded body and its \
+                           Content-Encoding unchanged"))
+            .with_upstream("/encodings/gzip", resource("gzip", "max-age=0"))
+            .with_reference(7230, "5.7.2")
+            .with_tags(vec!["encoding"])
+            .with_explanation(
+                "The response is gzip-encoded, and the client accepts gzip, \
+                 so the proxy has no reason to touch it. RFC 7230 §5.7.2 lets \
+                 a proxy transform a payload only when it's been set up to. \
+                 The client must get the upstream's exact bytes, with the \
+                 same Content-Encoding, and a Content-Length that matches \
+                 them.");
+
+    pub static ref DEFLATE_BODY: Test =
+        Test::new( "Encoding 2"
+                 , "Response body with `Content-Encoding: deflate`"
+                 , get("/encodings/deflate", "deflate")
+                 , expect(transparent("deflate", false),
+                          "Proxy must forward an encoded body and its \
+                           Content-Encoding unchanged"))
+            .with_upstream("/encodings/deflate",
+                           resource("deflate", "max-age=0"))
+            .with_reference(7230, "5.7.2")
+            .with_tags(vec!["encoding"])
+            .with_explanation(
+                "As Encoding 1, with the `deflate` coding, which is the zlib \
+                 format (RFC 7230 §4.2.2).");
+
+    pub static ref BROTLI_BODY: Test =
+        Test::new( "Encoding 3"
+                 , "Response body with `Content-Encoding: br`"
+                 , get("/encodings/br", "br")
+                 , expect(transparent("br", false),
+                          "Proxy must forward an encoded body and its \
+                           Content-Encoding unchanged"))
+            .with_upstream("/encodings/br", resource("br", "max-age=0"))
+            .with_reference(7230, "5.7.2")
+            .with_tags(vec!["encoding"])
+            .with_explanation(
+                "As Encoding 1, with the `br` coding (RFC 7932). Proxies that \
+                 only know gzip and deflate sometimes mangle codings they \
+                 don't recognise, or strip the Content-Encoding.");
+
+    pub static ref DOUBLE_ENCODED_BODY: Test =
+        Test::new( "Encoding 4"
+                 , "Response body with `Content-Encoding: gzip, br`"
+                 , get("/encodings/double", "gzip, br")
+                 , expect(transparent("gzip, br", false),
+                          "Proxy must forward an encoded body and its \
+                           Content-Encoding unchanged"))
+            .with_upstream("/encodings/double",
+                           resource("gzip, br", "max-age=0"))
+            .with_reference(7231, "3.1.2.2")
+            .with_tags(vec!["encoding"])
+            .with_explanation(
+                "`Content-Encoding: gzip, br` means gzip was applied first \
+                 and then brotli (RFC 7231 §3.1.2.2). A proxy that decodes \
+                 only the outer coding, or reorders the list, hands the \
+                 client bytes it can't decode. The proxy must forward the \
+                 body and the header exactly.");
+
+    pub static ref UNACCEPTED_ENCODING: Test =
+        Test::new( "Encoding 5"
+                 , "Response encoded with a coding the client didn't accept"
+                 , get("/encodings/unaccepted", "identity")
+                 , expect(transparent("gzip", true),
+                          "Proxy must forward an encoded body unchanged, or \
+                           decode it and drop its Content-Encoding"))
+            .with_upstream("/encodings/unaccepted",
+                           resource("gzip", "max-age=0"))
+            .with_reference(7231, "5.3.4")
+            .with_tags(vec!["encoding"])
+            .with_explanation(
+                "The client only accepts `identity`, but the upstream sends \
+                 gzip anyway. A proxy may decode the body for the client (RFC \
+                 7231 §5.3.4). If it does, it must drop the Content-Encoding \
+                 and send a Content-Length that matches the decoded body. A \
+                 proxy that isn't set up to do that may forward the response \
+                 unchanged.");
+
+    pub static ref NO_TRANSFORM: Test =
+        Test::new( "Encoding 6"
+                 , "Encoded response with `Cache-Control: no-transform`"
+                 , get("/encodings/no_transform", "identity")
+                 , expect(transparent("gzip", false),
+                          "Proxy must not transform the body of a \
+                           `no-transform` response"))
+            .with_upstream("/encodings/no_transform",
+                           resource("gzip", "no-transform"))
+            .with_reference(7234, "5.2.2.4")
+            .with_tags(vec!["encoding", "caching"])
+            .with_explanation(
+                "The upstream sends gzip to a client that only accepts \
+                 `identity`, but with `Cache-Control: no-transform`. RFC 7234 \
+                 §5.2.2.4 and RFC 7230 §5.7.2 say an intermediary must not \
+                 transform the payload of such a response, so even a proxy \
+                 that would normally decode the body must forward it as it \
+                 is.");
+
+    pub static ref ACCEPT_ENCODING_RESPECTED: Test =
+        Test::new( "Encoding 7"
+                 , "Request with `Accept-Encoding: identity` to an upstream \
+                    that negotiates"
+                 , get("/encodings/negotiated", "identity")
+                 // the proxy may ask the upstream for gzip, but then it
+                 // should decode the body itself
+                 , expect(all_of(vec![ complete_ok()
+                                     , header_absent("Content-Encoding")
+                                     , body_eq(identity())
+                                     , length_consistent()
+                                     ]),
+                          "Proxy should not send a content-coding the \
+                           client didn't accept"))
+            .with_upstream("/encodings/negotiated", |request: &Received| {
+                let gzip = request.header("Accept-Encoding")
+                    .map(|accept| String::from_utf8_lossy(accept)
+                                      .contains("gzip"))
+                    .unwrap_or(false);
+                let response = Response::new()
+                    .header("Vary", "Accept-Encoding");
+                Script::respond(if gzip {
+                    response.header("Content-Encoding", "gzip")
+                            .body(encoded("gzip"))
+                } else {
+                    response.body(identity())
+                })
+            })
+            .with_reference(7231, "5.3.4")
+            .with_level(Level::Should)
+            .with_tags(vec!["encoding"])
+            .with_explanation(
+                "The client only accepts `identity`, and the upstream sends \
+                 gzip only when the request accepts it. A proxy may ask the \
+                 upstream for gzip on the client's behalf, but then it should \
+                 decode the body itself. RFC 7231 §5.3.4 says a server whose \
+                 codings the client doesn't accept should send the response \
+                 without one, and a proxy that adds a coding on the way back \
+                 leaves the client with a body it said it can't handle.");
+
+    pub static ref NO_CONTENT_ENCODED: Test =
+        Test::new( "Encoding 8"
+                 , "204 No Content with `Content-Encoding: gzip`"
+                 , get("/encodings/no_content", "gzip")
+                 , expect(all_of(vec![status_is(204), no_body()]),
+                          "Proxy must forward a 204 No Content without a \
+                           body"))
+            .with_upstream("/encodings/no_content", |_: &Received| {
+                Script::respond(Response::new()
+                    .status_code(204, "No Content")
+                    .header("Content-Encoding", "gzip"))
+            })
+            .with_reference(7230, "3.3.3")
+            .with_tags(vec!["encoding", "framing"])
+            .with_explanation(
+                "A 204 No Content can have a Content-Encoding, but it never \
+                 has a body (RFC 7230 §3.3.3). A proxy that tries to encode \
+                 or decode a body that isn't there may send one anyway, such \
+                 as an empty gzip stream. Those bytes would then be read as \
+                 the start of the next response.");
+
+    pub static ref HEAD_ENCODED: Test = {
+        let mut request = get("/encodings/head", "gzip");
+        request.with_verb(Verb::Head);
+        Test::new( "Encoding 9"
+                 , "`HEAD` request for a gzip-encoded representation"
+                 , request
+                 , expect(all_of(vec![ status_is(200)
+                                     , no_body()
+                                     , head_length()
+                                     ]),
+                          "Proxy must not send a body in response to `HEAD`, \
+                           and its Content-Length must match its \
+                           Content-Encoding"))
+            .with_upstream("/encodings/head", |_: &Received| {
+                Script::respond(Response::new()
+                    .header("Content-Encoding", "gzip")
+                    .header("Content-Length",
+                            encoded("gzip").len().to_string()))
+            })
+            .with_reference(7231, "4.3.2")
+            .with_tags(vec!["encoding", "framing"])
+            .with_explanation(
+                "The response to a `HEAD` has the Content-Encoding and \
+                 Content-Length a `GET` would have had, but no body (RFC 7231 \
+                 §4.3.2). The proxy must not send a body, and if it changes \
+                 the coding, the Content-Length has to change with it.")
+    };
+}
@@ -0,0 +1,372 @@
+//! Sending requests to the proxy and reading its responses.
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::{Core, Handle, Timeout};
+use tokio_io::io;
+use net2::TcpBuilder;
+use futures::{Async, Future, Poll};
+use futures::future;
+use futures::stream::{self, Stream};
+
+use std::io::{Error, ErrorKind, Read, Result};
+use std::mem;
+use std::net::{Shutdown, SocketAddr};
+use std::time::{Duration, Instant};
+
+use httparse::{self, EMPTY_HEADER, Response};
+
+use upstream::Received;
+
+use super::{Delivery, Finish, Profile};
+
+/// Open a new connection to the proxy.
+pub fn connect(proxy_addr: &SocketAddr, handle: &Handle)
+               -> Result<Box<Future<Item=TcpStream, Error=Error> + Send>> {
+    let tcp =
+        TcpBuilder::new_v4()?
+            .reuse_address(true)?
+            .to_tcp_stream()?;
+    Ok(TcpStream::connect_stream(tcp, proxy_addr, handle))
+}
+
+/// Send `request` to the proxy in a single write on a new connection,
+/// and wait for the response.
+pub fn send( core: &mut Core, proxy_addr: &SocketAddr, request: &[u8]
+           , timeout: Duration)
+           -> Result<Exchange> {
+    let handle = core.handle();
+    let request = request.to_vec();
+    let exchange = connect(proxy_addr, &handle)?
+        .and_then(move |socket|
+            exchange(socket, &request, &Delivery::whole(), Finish::Open,
+                     timeout, handle));
+    core.run(exchange)
+}
+
+/// Returns a future that sends `request` on `socket` using `delivery`,
+/// and then reads the proxy's response until the proxy hangs up or
+/// `timeout` elapses.
+///
+/// If `finish` is `Finish::Reset`, the connection is reset instead of
+/// reading the response, and the future completes once `timeout` has
+/// elapsed, to give the proxy time to react.
+pub fn exchange( socket: TcpStream, request: &[u8], delivery: &Delivery
+               , finish: Finish, timeout: Duration, handle: Handle)
+               -> impl Future<Item=Exchange, Error=Error> {
+    let segments = delivery.segments(request);
+    let delay = delivery.delay;
+    debug!("delivering request in {} segment(s)", segments.len());
+    let read_handle = handle.clone();
+
+    // send the request one segment at a time. nagle's algorithm would
+    // coalesce our writes, so disable it.
+    let request = future::result(socket.set_nodelay(true))
+        .and_then(move |_| {
+            stream::iter_ok::<_, Error>(segments.into_iter().enumerate())
+                .fold(socket, move |socket, (i, segment)| {
+                    let pause: Box<Future<Item=(), Error=Error>> =
+                        match delay {
+                            Some(delay) if i > 0 => Box::new(
+                                future::result(
+                                    Timeout::new(delay, &handle))
+                                    .flatten())
+                          , _ => Box::new(future::ok(()))
+                        };
+                    pause.and_then(move |_| io::write_all(socket, segment))
+                         .map(|(socket, _)| socket)
+                })
+        });
+
+    // read everything the proxy sends back, until it hangs up or we
+    // time out
+    request.and_then(move |socket| {
+        trace!("sent request on {:?}", socket);
+        let timeout = Timeout::new(timeout, &read_handle);
+        let finished = match finish {
+            Finish::Open => Ok(())
+          , Finish::HalfClose => socket.shutdown(Shutdown::Write)
+          , // with a zero linger timeout, closing the socket sends a RST
+            Finish::Reset => socket.set_linger(Some(Duration::from_secs(0)))
+        };
+        future::result(finished.and(timeout))
+            .and_then(move |timeout| {
+                let mut read = ReadResponse::new(socket, timeout);
+                if finish == Finish::Reset {
+                    read.abort();
+                }
+                read
+            })
+    })
+}
+
+/// How the proxy's side of the connection ended.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum End {
+    /// the proxy closed the connection cleanly
+    Closed
+  , /// the proxy reset the connection
+    Reset
+  , /// the proxy was still connected when the test timed out
+    TimedOut
+  , /// we reset the connection without reading the response
+    Aborted
+}
+
+/// Everything the proxy sent back in response to a test request.
+#[derive(Clone, Debug)]
+pub struct Exchange {
+    /// the bytes the proxy sent
+    pub response: Vec<u8>
+  , /// how the connection ended
+    pub end: End
+  , /// how long after the request was sent each read from the proxy
+    /// completed, and how many bytes had been recieved in total by then
+    pub arrivals: Vec<(Duration, usize)>
+  , /// how long after the request was sent the connection ended
+    pub elapsed: Duration
+  , /// the requests the upstream recieved for this test
+    pub upstream: Vec<Received>
+  , /// how the proxy is deployed
+    pub profile: Profile
+  , /// the upstream's address, as the test's request names it
+    pub authority: String
+}
+
+impl Exchange {
+    /// how long after the request was sent the first byte of the
+    /// response arrived
+    pub fn first_byte(&self) -> Option<Duration> {
+        self.arrivals.first().map(|&(at, _)| at)
+    }
+
+    /// how many bytes of the response had arrived `after` the request
+    /// was sent
+    pub fn received_by(&self, after: Duration) -> usize {
+        self.arrivals.iter()
+            .take_while(|&&(at, _)| at <= after)
+            .last()
+            .map(|&(_, total)| total)
+            .unwrap_or(0)
+    }
+
+    /// Returns the response's status code, if its status line has been
+    /// recieved.
+    pub fn status_code(&self) -> Option<u16> {
+        let mut headers = [EMPTY_HEADER; 64];
+        let mut parsed = Response::new(&mut headers);
+        // a partial parse still fills in the status line
+        let _ = parsed.parse(&self.response);
+        parsed.code
+    }
+
+    /// Returns the values of every response header named `name`,
+    /// ignoring case, if the whole response head has been recieved.
+    pub fn headers_named(&self, name: &str) -> Vec<Vec<u8>> {
+        let mut headers = [EMPTY_HEADER; 64];
+        let mut parsed = Response::new(&mut headers);
+        match parsed.parse(&self.response) {
+            Ok(httparse::Status::Complete(_)) => parsed.headers.iter()
+                .filter(|h| h.name.eq_ignore_ascii_case(name))
+                .map(|h| h.value.to_vec())
+                .collect()
+          , _ => Vec::new()
+        }
+    }
+
+    /// Returns the value of the first response header named `name`,
+    /// ignoring case.
+    pub fn header(&self, name: &str) -> Option<Vec<u8>> {
+        self.headers_named(name).into_iter().next()
+    }
+
+    /// Returns the length of the response head, if all of it has been
+    /// recieved.
+    pub fn head_len(&self) -> Option<usize> {
+        let mut headers = [EMPTY_HEADER; 64];
+        let mut parsed = Response::new(&mut headers);
+        match parsed.parse(&self.response) {
+            Ok(httparse::Status::Complete(len)) => Some(len)
+          , _ => None
+        }
+    }
+
+    /// Returns true if the response is complete according to its own
+    /// framing, i.e. a client would have no way to tell that anything is
+    /// missing from it.
+    pub fn is_complete(&self) -> bool {
+        let (head_len, framing) = match self.framing() {
+            Some(framing) => framing
+          , None => return false
+        };
+        let body = &self.response[head_len..];
+        match framing {
+            Framing::Empty => true
+          , Framing::Chunked => chunked_complete(body)
+          , Framing::Length(length) => body.len() >= length
+          , Framing::Close => self.end == End::Closed
+        }
+    }
+
+    /// Returns the response body with any chunked encoding removed, if
+    /// the whole response has been recieved.
+    pub fn body(&self) -> Option<Vec<u8>> {
+        if !self.is_complete() { return None }
+        let (head_len, framing) = match self.framing() {
+            Some(framing) => framing
+          , None => return None
+        };
+        let body = &self.response[head_len..];
+        match framing {
+            Framing::Empty => Some(Vec::new())
+          , Framing::Chunked => dechunk(body)
+          , Framing::Length(length) => Some(body[..length].to_vec())
+          , Framing::Close => Some(body.to_vec())
+        }
+    }
+
+    /// Returns the length of the response head, and how the end of the
+    /// body is found, if all of the head has been recieved.
+    fn framing(&self) -> Option<(usize, Framing)> {
+        let mut headers = [EMPTY_HEADER; 64];
+        let mut parsed = Response::new(&mut headers);
+        let head_len = match parsed.parse(&self.response) {
+            Ok(httparse::Status::Complete(len)) => len
+          , _ => return None
+        };
+
+        let chunked = parsed.headers.iter()
+            .filter(|h| h.name.eq_ignore_ascii_case("Transfer-Encoding"))
+            .any(|h| String::from_utf8_lossy(h.value)
+                        .to_lowercase()
+                        .contains("chunked"));
+        let length = parsed.headers.iter()
+            .find(|h| h.name.eq_ignore_ascii_case("Content-Length"))
+            .and_then(|h| String::from_utf8_lossy(h.value).trim()
+                              .parse::<usize>().ok());
+
+        let framing = match (parsed.code, chunked, length) {
+            (Some(code), _, _) if code < 200 || code == 204 || code == 304 =>
+                Framing::Empty
+          , (_, true, _) => Framing::Chunked
+          , (_, false, Some(length)) => Framing::Length(length)
+          , // the body is delimited by the connection closing
+            (_, false, None) => Framing::Close
+        };
+        Some((head_len, framing))
+    }
+}
+
+/// how the end of a response body is found
+#[derive(Copy, Clone, Debug)]
+enum Framing {
+    /// the response has no body
+    Empty
+  , Chunked
+  , Length(usize)
+  , /// the body ends when the connection closes
+    Close
+}
+
+/// returns true if `body` contains a complete chunked message body
+fn chunked_complete(body: &[u8]) -> bool {
+    dechunk(body).is_some()
+}
+
+/// returns the contents of the chunks in `body`, if it contains a
+/// complete chunked message body
+fn dechunk(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    loop {
+        let line_end = match body.windows(2).position(|w| w == b"\r\n") {
+            Some(i) => i
+          , None => return None
+        };
+        let size = String::from_utf8_lossy(&body[..line_end]);
+        let size = size.split(';').next().unwrap_or("").trim();
+        let size = match usize::from_str_radix(size, 16) {
+            Ok(size) => size
+          , Err(_) => return None
+        };
+        body = &body[line_end + 2..];
+        if size == 0 {
+            // the last chunk is followed by the trailer and an empty line
+            let ended = body.starts_with(b"\r\n") ||
+                        body.windows(4).any(|w| w == b"\r\n\r\n");
+            return if ended { Some(decoded) } else { None }
+        }
+        if body.len() < size + 2 { return None }
+        decoded.extend_from_slice(&body[..size]);
+        body = &body[size + 2..];
+    }
+}
+
+/// A future that reads everything the proxy sends until the connection
+/// ends or the test times out.
+pub struct ReadResponse {
+    socket: Option<TcpStream>
+  , response: Vec<u8>
+  , arrivals: Vec<(Duration, usize)>
+  , started: Instant
+  , timeout: Timeout
+}
+
+impl ReadResponse {
+    pub fn new(socket: TcpStream, timeout: Timeout) -> Self {
+        ReadResponse { socket: Some(socket)
+                     , response: Vec::new()
+                     , arrivals: Vec::new()
+                     , started: Instant::now()
+                     , timeout: timeout
+                     }
+    }
+
+    /// close the connection, and just wait for the timeout
+    fn abort(&mut self) {
+        self.socket.take();
+    }
+
+    fn finish(&mut self, end: End) -> Async<Exchange> {
+        trace!("response ended: {:?}", end);
+        Async::Ready(Exchange {
+            response: mem::replace(&mut self.response, Vec::new())
+          , end: end
+          , arrivals: mem::replace(&mut self.arrivals, Vec::new())
+          , elapsed: self.started.elapsed()
+          , upstream: Vec::new()
+          , profile: Profile::default()
+          , authority: String::new()
+        })
+    }
+}
+
+impl Future for ReadResponse {
+    type Item = Exchange;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Exchange, Error> {
+        let mut buf = [0; 8192];
+        loop {
+            let read = match self.socket {
+                Some(ref mut socket) => socket.read(&mut buf)
+              , None => break
+            };
+            match read {
+                Ok(0) => return Ok(self.finish(End::Closed))
+              , Ok(n) => {
+                    self.response.extend_from_slice(&buf[..n]);
+                    let total = self.response.len();
+                    self.arrivals.push((self.started.elapsed(), total));
+                }
+              , Err(ref e) if e.kind() == ErrorKind::WouldBlock => break
+              , Err(ref e) if e.kind() == ErrorKind::ConnectionReset =>
+                    return Ok(self.finish(End::Reset))
+              , Err(e) => return Err(e)
+            }
+        }
+        match self.timeout.poll()? {
+            Async::Ready(()) if self.socket.is_none() =>
+                Ok(self.finish(End::Aborted))
+          , Async::Ready(()) => Ok(self.finish(End::TimedOut))
+          , Async::NotReady => Ok(Async::NotReady)
+        }
+    }
+}
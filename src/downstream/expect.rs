@@ -0,0 +1,346 @@
+//! Assertions about what the proxy did, for writing checks.
+//!
+//! Most checks ask the same few questions of an exchange: what status
+//! the proxy responded with, which headers it sent, what the body was,
+//! and what reached the upstream. Each assertion here answers one of
+//! them, and they combine with `all_of` and `any_of`, so a check can be
+//! written as a single expression:
+//!
+//! ```ignore
+//! expect( all_of(vec![ upstream_received("a `get` request", is_get)
+//!                    , status_is(200) ])
+//!       , "Proxy must not change the case of the request method")
+//! ```
+//!
+//! When an assertion doesn't hold, the test fails with what it expected
+//! and what it observed instead.
+use std::io::Result;
+use std::time::Duration;
+
+use upstream::Received;
+
+use super::{End, Exchange, Outcome, Status};
+
+/// the most bytes of a body to show in a failure message
+const PREVIEW_LEN: usize = 64;
+
+/// What an assertion expected, and what it observed instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    pub expected: String
+  , pub actual: String
+}
+
+/// Something that should be true of an exchange.
+pub struct Assertion(Box<Fn(&Exchange) -> Option<Mismatch> + Sync>);
+
+impl Assertion {
+    /// Returns an assertion that holds unless `check` returns what it
+    /// expected and observed instead.
+    pub fn new<F>(check: F) -> Self
+    where F: Fn(&Exchange) -> Option<Mismatch> + Sync + 'static {
+        Assertion(Box::new(check))
+    }
+
+    /// Returns what was expected and observed instead, if this assertion
+    /// doesn't hold for `exchange`.
+    pub fn check(&self, exchange: &Exchange) -> Option<Mismatch> {
+        (self.0)(exchange)
+    }
+}
+
+/// Returns a test check that passes if `assertion` holds, and fails
+/// with `why` if it doesn't.
+pub fn expect(assertion: Assertion, why: &'static str)
+              -> impl Fn(&Exchange) -> Result<Status> + Sync {
+    move |exchange: &Exchange| {
+        let status = match assertion.check(exchange) {
+            None => Status::Passed
+          , Some(mismatch) => Status::Unexpected {
+                why: why
+              , expected: mismatch.expected
+              , actual: mismatch.actual
+              , bytes: exchange.response.clone()
+            }
+        };
+
+        Ok(status)
+    }
+}
+
+/// the start of `bytes`, quoted, for a failure message
+fn preview(bytes: &[u8]) -> String {
+    if bytes.len() > PREVIEW_LEN {
+        format!( "{:?}... ({} bytes)"
+               , String::from_utf8_lossy(&bytes[..PREVIEW_LEN])
+               , bytes.len())
+    } else {
+        format!("{:?}", String::from_utf8_lossy(bytes))
+    }
+}
+
+/// Describes the status the proxy responded with in `exchange`, or how
+/// the connection ended if it never sent one, in the same terms as
+/// `Outcome`.
+pub fn observed_status(exchange: &Exchange) -> String {
+    match (exchange.status_code(), exchange.end) {
+        (Some(code), _) => format!("status {}", code)
+      , (None, End::Closed) => "connection closed".to_owned()
+      , (None, End::Reset) => "connection reset".to_owned()
+      , (None, End::TimedOut) => "no response before the timeout".to_owned()
+      , (None, End::Aborted) => "no response read".to_owned()
+    }
+}
+
+/// Describes the requests the upstream recieved in `exchange`.
+fn observed_requests(exchange: &Exchange) -> String {
+    if exchange.upstream.is_empty() {
+        return "nothing reached the upstream".to_owned()
+    }
+    exchange.upstream.iter()
+        .map(|request| {
+            let mut observed = format!( "{} {}"
+                , String::from_utf8_lossy(&request.method)
+                , String::from_utf8_lossy(&request.target));
+            if !request.body.is_empty() {
+                observed.push_str(&format!( " with a {}-byte body"
+                                          , request.body.len()));
+            }
+            observed
+        })
+        .collect::<Vec<_>>()
+        .join(", then ")
+}
+
+/// Describes the headers named `name` with the values `values`.
+pub fn observed_headers(name: &str, values: &[Vec<u8>]) -> String {
+    if values.is_empty() {
+        return format!("no {} header", name)
+    }
+    values.iter()
+        .map(|value| format!("{}: {}", name, String::from_utf8_lossy(value)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The proxy responded with status `code`.
+pub fn status_is(code: u16) -> Assertion {
+    Assertion::new(move |exchange: &Exchange| {
+        if exchange.status_code() == Some(code) { return None }
+        Some(Mismatch { expected: format!("status {}", code)
+                      , actual: observed_status(exchange)
+                      })
+    })
+}
+
+/// The proxy did `outcome`.
+pub fn outcome(outcome: Outcome) -> Assertion {
+    Assertion::new(move |exchange: &Exchange| {
+        if outcome.observed_in(exchange) { return None }
+        let actual = match outcome {
+            Outcome::NotForwarded => observed_requests(exchange)
+          , _ => observed_status(exchange)
+        };
+        Some(Mismatch { expected: outcome.to_string(), actual: actual })
+    })
+}
+
+/// The response has no header named `name`.
+pub fn header_absent(name: &'static str) -> Assertion {
+    Assertion::new(move |exchange: &Exchange| {
+        let values = exchange.headers_named(name);
+        if values.is_empty() { return None }
+        Some(Mismatch { expected: format!("no {} header", name)
+                      , actual: observed_headers(name, &values)
+                      })
+    })
+}
+
+/// The response has exactly one header named `name`, and its value is
+/// `value`.
+pub fn header_eq(name: &'static str, value: &'static str) -> Assertion {
+    header_where(name, format!("{}: {}", name, value), move |values| {
+        values.len() == 1 && values[0] == value.as_bytes()
+    })
+}
+
+/// The values of the response's headers named `name` match `matches`,
+/// which is described by `what`.
+pub fn header_matches<F>(name: &'static str, what: &'static str, matches: F)
+                         -> Assertion
+where F: Fn(&[Vec<u8>]) -> bool + Sync + 'static {
+    header_where(name, what.to_owned(), matches)
+}
+
+/// the values of the response's headers named `name` match `matches`,
+/// which is described by `expected`
+fn header_where<F>(name: &'static str, expected: String, matches: F)
+                   -> Assertion
+where F: Fn(&[Vec<u8>]) -> bool + Sync + 'static {
+    Assertion::new(move |exchange: &Exchange| {
+        let values = exchange.headers_named(name);
+        if matches(&values) { return None }
+        Some(Mismatch { expected: expected.clone()
+                      , actual: observed_headers(name, &values)
+                      })
+    })
+}
+
+/// The proxy sent a complete response, according to its own framing.
+pub fn complete() -> Assertion {
+    Assertion::new(|exchange: &Exchange| {
+        if exchange.is_complete() { return None }
+        Some(Mismatch { expected: "a complete response".to_owned()
+                      , actual: "an incomplete response".to_owned()
+                      })
+    })
+}
+
+/// The proxy sent a response that ends before its own framing says it
+/// should.
+pub fn incomplete() -> Assertion {
+    Assertion::new(|exchange: &Exchange| {
+        if !exchange.is_complete() { return None }
+        Some(Mismatch { expected: "an incomplete response".to_owned()
+                      , actual: "a complete response".to_owned()
+                      })
+    })
+}
+
+/// The proxy sent a complete 200 OK response.
+pub fn complete_ok() -> Assertion {
+    all_of(vec![status_is(200), complete()])
+}
+
+/// The response ends with its head, with no body after it.
+pub fn no_body() -> Assertion {
+    Assertion::new(|exchange: &Exchange| {
+        let actual = match exchange.head_len() {
+            Some(head_len) if head_len == exchange.response.len() =>
+                return None
+          , Some(head_len) => format!( "{} bytes of body"
+                                     , exchange.response.len() - head_len)
+          , None => "an incomplete head".to_owned()
+        };
+        Some(Mismatch { expected: "no body".to_owned(), actual: actual })
+    })
+}
+
+/// Some of the body arrived within `by` of sending the request.
+pub fn streamed(by: Duration) -> Assertion {
+    Assertion::new(move |exchange: &Exchange| {
+        let head_len = exchange.head_len().unwrap_or(0);
+        let early = exchange.received_by(by).saturating_sub(head_len);
+        if early > 0 { return None }
+        let millis = by.as_secs() * 1000
+                   + (by.subsec_nanos() / 1_000_000) as u64;
+        Some(Mismatch { expected: format!( "some of the body within {} ms"
+                                         , millis)
+                      , actual: "none of it".to_owned()
+                      })
+    })
+}
+
+/// The response is complete, and its body, once any chunked encoding
+/// is removed, is exactly `body`.
+pub fn body_eq<B: Into<Vec<u8>>>(body: B) -> Assertion {
+    let body = body.into();
+    Assertion::new(move |exchange: &Exchange| {
+        let actual = match exchange.body() {
+            Some(ref actual) if actual == &body => return None
+          , Some(actual) => preview(&actual)
+          , None => "an incomplete response".to_owned()
+        };
+        Some(Mismatch { expected: preview(&body), actual: actual })
+    })
+}
+
+/// Some request the upstream recieved matches `matches`, which is
+/// described by `what`.
+pub fn upstream_received<F>(what: &'static str, matches: F) -> Assertion
+where F: Fn(&Received) -> bool + Sync + 'static {
+    Assertion::new(move |exchange: &Exchange| {
+        if exchange.upstream.iter().any(|request| matches(request)) {
+            return None
+        }
+        Some(Mismatch { expected: format!("the upstream to recieve {}", what)
+                      , actual: observed_requests(exchange)
+                      })
+    })
+}
+
+/// The upstream recieved the request at least once.
+pub fn forwarded() -> Assertion {
+    Assertion::new(|exchange: &Exchange| {
+        if !exchange.upstream.is_empty() { return None }
+        Some(Mismatch { expected: "forwarded to the upstream".to_owned()
+                      , actual: observed_requests(exchange)
+                      })
+    })
+}
+
+/// The requests the upstream recieved, taken together, match `matches`,
+/// which is described by `what`.
+pub fn upstream_requests<F>(what: &'static str, matches: F) -> Assertion
+where F: Fn(&[Received]) -> bool + Sync + 'static {
+    Assertion::new(move |exchange: &Exchange| {
+        if matches(&exchange.upstream) { return None }
+        Some(Mismatch { expected: format!("the upstream to recieve {}", what)
+                      , actual: observed_requests(exchange)
+                      })
+    })
+}
+
+/// In every request the upstream recieved, the values of the headers
+/// named `name` match `matches`, which is described by `what`. This
+/// holds if nothing reached the upstream at all.
+pub fn upstream_header<F>(name: &'static str, what: &'static str, matches: F)
+                          -> Assertion
+where F: Fn(&[Vec<u8>]) -> bool + Sync + 'static {
+    Assertion::new(move |exchange: &Exchange| {
+        let values = exchange.upstream.iter()
+            .map(|request| request.headers_named(name)
+                                  .map(|value| value.to_vec())
+                                  .collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        if values.iter().all(|values| matches(values)) { return None }
+        let actual = values.iter()
+            .map(|values| observed_headers(name, values))
+            .collect::<Vec<_>>()
+            .join(", then ");
+        Some(Mismatch { expected: format!("the upstream to recieve {}", what)
+                      , actual: actual
+                      })
+    })
+}
+
+/// At least one of `assertions` holds.
+pub fn any_of(assertions: Vec<Assertion>) -> Assertion {
+    Assertion::new(move |exchange: &Exchange| {
+        let mut expected = Vec::new();
+        let mut actual = Vec::<String>::new();
+        for assertion in &assertions {
+            let mismatch = match assertion.check(exchange) {
+                None => return None
+              , Some(mismatch) => mismatch
+            };
+            expected.push(mismatch.expected);
+            if !actual.contains(&mismatch.actual) {
+                actual.push(mismatch.actual);
+            }
+        }
+        Some(Mismatch { expected: expected.join(" or ")
+                      , actual: actual.join("; ")
+                      })
+    })
+}
+
+/// Every one of `assertions` holds. Only the first that doesn't is
+/// reported.
+pub fn all_of(assertions: Vec<Assertion>) -> Assertion {
+    Assertion::new(move |exchange: &Exchange| {
+        assertions.iter()
+            .filter_map(|assertion| assertion.check(exchange))
+            .next()
+    })
+}
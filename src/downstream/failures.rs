@@ -0,0 +1,181 @@
+//! Tests for how the proxy handles an upstream that fails.
+//!
+//! When the upstream can't be reached or hangs up without sending a
+//! complete response, the proxy should answer with 502 Bad Gateway, or
+//! 504 Gateway Timeout if the upstream never answers (RFC 7231 §6.6).
+//! If the proxy has already started forwarding a response when the
+//! upstream goes away, it can't change the status any more, but it must
+//! not make the truncated response look complete to the client
+//! (RFC 7230 §3.3.3).
+use std::time::Duration;
+
+use upstream::{Received, Response, Script};
+
+use super::{Kind, Level, RawRequest, Request, Test};
+use super::checks::truncation_visible;
+use super::expect::{expect, status_is};
+
+lazy_static! {
+    pub static ref UPSTREAM_REFUSED: Test = {
+        // a forward proxy is sent the absolute-form of the URI it should
+        // connect to
+        let mut request = RawRequest::new();
+        request.with_template("GET http://{host}/failures/refused HTTP/1.1")
+               .with_host_line()
+               .with_line("Connection: close")
+               .with_line("");
+        Test::new( "Upstream Failure 1"
+                 , "Upstream refuses the connection"
+                 , request
+                 , expect(status_is(502),
+                          "Proxy response status must be 502 Bad Gateway"))
+               .only_for(Kind::Forward,
+                   "a reverse proxy connects to the upstream it's \
+                    configured with, whatever the request names, so it \
+                    can't be sent to one that refuses connections")
+               .with_unreachable_upstream()
+               .with_reference(7231, "6.6.3")
+               .with_level(Level::Should)
+               .with_tags(vec!["failures"])
+               .with_explanation(
+                   "The request names an upstream that nothing is listening \
+                    on. A gateway that can't get a response from the server \
+                    it was asked to reach should say so with 502 Bad Gateway \
+                    (RFC 7231 §6.6.3), so the client can tell the proxy is \
+                    fine and the upstream isn't.\n\
+                    \n\
+                    Only a forward proxy picks its upstream from the request, \
+                    so the test is skipped for reverse proxies.")
+    };
+
+    pub static ref UPSTREAM_RESET: Test = {
+        let mut request = Request::new();
+        request.with_path("/failures/reset")
+               .with_header("Connection: close");
+        Test::new( "Upstream Failure 2"
+                 , "Upstream resets the connection without responding"
+                 , request
+                 , expect(status_is(502),
+                          "Proxy response status must be 502 Bad Gateway"))
+            .with_upstream("/failures/reset", |_: &Received| {
+                Script::new().reset()
+            })
+            .with_reference(7231, "6.6.3")
+            .with_level(Level::Should)
+            .with_tags(vec!["failures"])
+            .with_explanation(
+                "The upstream accepts the connection and reads the request, \
+                 then resets the connection without sending anything. The \
+                 proxy got an invalid response, or none at all, and should \
+                 answer 502 Bad Gateway (RFC 7231 §6.6.3).")
+    };
+
+    pub static ref UPSTREAM_CLOSED_AFTER_HEADERS: Test = {
+        let mut request = Request::new();
+        request.with_path("/failures/headers_only")
+               .with_header("Connection: close");
+        Test::new( "Upstream Failure 3"
+                 , "Upstream closes the connection after the response headers"
+                 , request
+                 , truncation_visible())
+            .with_upstream("/failures/headers_only", |_: &Received| {
+                Script::new()
+                    .send(Response::new().header("Content-Length", "100"))
+                    .close()
+            })
+            .with_reference(7230, "3.4")
+            .with_tags(vec!["failures", "framing"])
+            .with_explanation(
+                "The upstream sends headers promising a 100-byte body, then \
+                 closes the connection without sending any of it. By then the \
+                 proxy may already have forwarded the headers, so it can't \
+                 change the status to 502. It still must not let the client \
+                 think the response is complete.\n\
+                 \n\
+                 RFC 7230 §3.4 says a message whose body is shorter than its \
+                 Content-Length is incomplete. The proxy has to pass that on, \
+                 usually by closing the client connection before the body is \
+                 complete, rather than padding the body or fixing up the \
+                 length.")
+    };
+
+    pub static ref UPSTREAM_TRUNCATED_LENGTH: Test = {
+        let mut request = Request::new();
+        request.with_path("/failures/truncated_length")
+               .with_header("Connection: close");
+        Test::new( "Upstream Failure 4"
+                 , "Upstream closes the connection part-way through a \
+                    Content-Length body"
+                 , request
+                 , truncation_visible())
+            .with_upstream("/failures/truncated_length", |_: &Received| {
+                Script::new()
+                    .send(Response::new()
+                            .header("Content-Length", "100")
+                            .body(vec![b'a'; 50]))
+                    .close()
+            })
+            .with_reference(7230, "3.4")
+            .with_tags(vec!["failures", "framing"])
+            .with_explanation(
+                "The upstream sends half of a Content-Length body and then \
+                 closes the connection. The response is incomplete (RFC 7230 \
+                 §3.4). The proxy must either answer 502, or forward the \
+                 response in a way that still looks incomplete to the client, \
+                 not re-frame what it got as a whole response. A client that \
+                 caches or acts on a truncated response as if it were \
+                 complete can be made to use corrupted data.")
+    };
+
+    pub static ref UPSTREAM_TRUNCATED_CHUNKED: Test = {
+        let mut request = Request::new();
+        request.with_path("/failures/truncated_chunked")
+               .with_header("Connection: close");
+        Test::new( "Upstream Failure 5"
+                 , "Upstream closes the connection part-way through a \
+                    chunked body"
+                 , request
+                 , truncation_visible())
+            .with_upstream("/failures/truncated_chunked", |_: &Received| {
+                // one complete chunk, and no last chunk
+                Script::new()
+                    .send(Response::new()
+                            .header("Transfer-Encoding", "chunked")
+                            .body("19\r\naaaaaaaaaaaaaaaaaaaaaaaaa\r\n"))
+                    .close()
+            })
+            .with_reference(7230, "3.4")
+            .with_tags(vec!["failures", "framing"])
+            .with_explanation(
+                "The upstream sends one chunk of a chunked body and then \
+                 closes the connection without the last chunk. Only the \
+                 zero-length last chunk marks a chunked body complete (RFC \
+                 7230 §3.4), so the proxy must not send one to the client \
+                 unless the upstream did.")
+    };
+
+    pub static ref UPSTREAM_NEVER_RESPONDS: Test = {
+        let mut request = Request::new();
+        request.with_path("/failures/stall")
+               .with_header("Connection: close");
+        Test::new( "Upstream Failure 6"
+                 , "Upstream never responds"
+                 , request
+                 , expect(status_is(504),
+                          "Proxy response status must be 504 Gateway Timeout"))
+            .with_upstream("/failures/stall", |_: &Received| {
+                Script::new().stall()
+            })
+            // give the proxy's own timeout a chance to elapse
+            .with_timeout(Duration::from_secs(60))
+            .with_reference(7231, "6.6.5")
+            .with_level(Level::Should)
+            .with_tags(vec!["failures", "timeouts"])
+            .with_explanation(
+                "The upstream reads the request and never answers. Once the \
+                 proxy's own timeout runs out, it should tell the client with \
+                 504 Gateway Timeout (RFC 7231 §6.6.5) rather than leaving \
+                 the connection hanging. The test waits a minute for that to \
+                 happen.")
+    };
+}
@@ -0,0 +1,288 @@
+//! Tests for whether the proxy keeps header fields intact.
+//!
+//! A proxy may combine header fields with the same name into one, but
+//! only if the field is a comma-separated list, only by joining their
+//! values with commas, and only in the order they were recieved (RFC
+//! 7230 §3.2.2). Fields that aren't lists can't be combined at all:
+//! `Set-Cookie` values contain commas of their own, and RFC 6265 §3
+//! forbids folding them, while `Cookie` headers may only be joined with
+//! `; ` (RFC 6265 §5.4). Whatever the proxy does with names, a field's
+//! value is opaque to it, so every byte of it, including its case,
+//! quotes and any `obs-text`, must arrive as sent.
+//!
+//! The upstream records the raw header lines it recieves, so the tests
+//! can see exactly what the proxy sent.
+use upstream::{Received, Response, Script};
+
+use super::{Exchange, RawRequest, Test, get, ok};
+use super::expect::{ Assertion, Mismatch, all_of, complete_ok, expect
+                   , header_matches, observed_headers };
+
+/// a value with runs of whitespace, a quoted comma, mixed case and
+/// `obs-text`, none of which the proxy may touch
+const BYTES: &'static [u8] = b"MiXeD  \"q,u\\\"o\" \t tab \xe9\xff";
+
+/// strip optional whitespace from both ends of `value`
+fn trim(value: &[u8]) -> &[u8] {
+    let start = value.iter().position(|&b| b != b' ' && b != b'\t')
+                     .unwrap_or(value.len());
+    let end = value.iter().rposition(|&b| b != b' ' && b != b'\t')
+                   .map(|i| i + 1)
+                   .unwrap_or(start);
+    &value[start..end]
+}
+
+/// the values of the raw header lines named `name`, in the order the
+/// upstream recieved them
+fn raw_values(request: &Received, name: &str) -> Vec<Vec<u8>> {
+    request.raw_headers.iter()
+        .filter_map(|line| {
+            let colon = match line.iter().position(|&b| b == b':') {
+                Some(colon) => colon
+              , None => return None
+            };
+            let matches = String::from_utf8_lossy(&line[..colon])
+                .eq_ignore_ascii_case(name);
+            if matches { Some(trim(&line[colon + 1..]).to_vec()) }
+            else { None }
+        })
+        .collect()
+}
+
+/// the items in `values`, separated by `sep`, in order
+fn items(values: &[Vec<u8>], sep: u8) -> Vec<Vec<u8>> {
+    values.iter()
+        .flat_map(|value| value.split(|&b| b == sep)
+                               .map(|item| trim(item).to_vec())
+                               .collect::<Vec<_>>())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// returns true if `items` are exactly `expected`, in order
+fn items_are(items: &[Vec<u8>], expected: &[&str]) -> bool {
+    items.len() == expected.len() &&
+    items.iter().zip(expected).all(|(item, e)| &item[..] == e.as_bytes())
+}
+
+/// if the proxy forwarded the request, the values of the raw header
+/// lines named `name` match `matches`, which is described by `what`
+fn request_fields<F>(name: &'static str, what: &'static str, matches: F)
+                     -> Assertion
+where F: Fn(&[Vec<u8>]) -> bool + Sync + 'static {
+    Assertion::new(move |exchange: &Exchange| {
+        let values = match exchange.upstream.first() {
+            Some(request) => raw_values(request, name)
+          , None => return None
+        };
+        if matches(&values) { return None }
+        Some(Mismatch { expected: format!("the upstream to recieve {}", what)
+                      , actual: observed_headers(name, &values)
+                      })
+    })
+}
+
+/// the upstream recieved the list items in the fields named `name` as
+/// exactly `expected`, in order
+fn request_list(name: &'static str, expected: &'static [&'static str])
+                -> Assertion {
+    all_of(vec![ request_fields(name, "the same items, in order",
+                     move |values: &[Vec<u8>]|
+                         items_are(&items(values, b','), expected))
+               , complete_ok()
+               ])
+}
+
+/// the proxy sent the list items in the fields named `name` as exactly
+/// `expected`, in order
+fn response_list(name: &'static str, expected: &'static [&'static str])
+                 -> Assertion {
+    all_of(vec![ complete_ok()
+               , header_matches(name, "the same items, in order",
+                     move |values: &[Vec<u8>]|
+                         items_are(&items(values, b','), expected))
+               ])
+}
+
+lazy_static! {
+    pub static ref REQUEST_VALUE_BYTES: Test = {
+        let mut field = b"X-Flossy-Bytes: ".to_vec();
+        field.extend_from_slice(BYTES);
+        let mut request = RawRequest::new();
+        request.with_line("GET /fields/request_bytes HTTP/1.1")
+               .with_host_line()
+               .with_line(field)
+               .with_line("Connection: close")
+               .with_line("");
+        Test::new( "Fields 1"
+                 , "Request field value with mixed case, quotes and \
+                    `obs-text`"
+                 , request
+                 , expect(all_of(vec![
+                              request_fields("X-Flossy-Bytes",
+                                  "the value exactly as sent",
+                                  |values: &[Vec<u8>]|
+                                      values.len() == 1 && values[0] == BYTES)
+                            , complete_ok()
+                          ]),
+                          "Proxy must forward field values exactly as sent"))
+            .with_upstream("/fields/request_bytes", ok)
+            .with_reference(7230, "3.2")
+            .with_tags(vec!["fields"])
+            .with_explanation(
+                "A field value is opaque to a proxy. Its case, runs of \
+                 whitespace, quoted commas and `obs-text` bytes must all \
+                 reach the upstream exactly as sent, apart from whitespace at \
+                 either end, which isn't part of the value (RFC 7230 §3.2.4).")
+    };
+
+    pub static ref REQUEST_LIST_ORDER: Test =
+        Test::new( "Fields 2"
+                 , "Request with two `Accept-Language` fields"
+                 , get("/fields/request_list", &[
+                       "Accept-Language: en"
+                     , "X-Flossy-Between: 1"
+                     , "Accept-Language: fr;q=0.5, de;q=0.1"
+                   ])
+                 , expect(request_list( "Accept-Language"
+                                      , &["en", "fr;q=0.5", "de;q=0.1"]),
+                          "Proxy must keep the order of fields with the same \
+                           name, and only combine them with commas"))
+            .with_upstream("/fields/request_list", ok)
+            .with_reference(7230, "3.2.2")
+            .with_tags(vec!["fields"])
+            .with_explanation(
+                "`Accept-Language` is a list, so a proxy may combine two of \
+                 them into one, separated by a comma. It must keep the order \
+                 (RFC 7230 §3.2.2), because the order of a list's items can \
+                 change what it means.");
+
+    pub static ref REQUEST_DUPLICATE_ORDER: Test =
+        Test::new( "Fields 3"
+                 , "Request with an unknown field repeated three times"
+                 , get("/fields/request_repeated", &[
+                       "X-Flossy-Order: 1"
+                     , "X-Flossy-Order: 2"
+                     , "X-Flossy-Between: 1"
+                     , "X-Flossy-Order: 3"
+                   ])
+                 , expect(request_list("X-Flossy-Order", &["1", "2", "3"]),
+                          "Proxy must keep the order of fields with the same \
+                           name, and only combine them with commas"))
+            .with_upstream("/fields/request_repeated", ok)
+            .with_reference(7230, "3.2.2")
+            .with_tags(vec!["fields"])
+            .with_explanation(
+                "A field the proxy doesn't know appears three times, with \
+                 another field in between. The proxy must keep the three \
+                 values in order. If it combines them, it must use commas \
+                 (RFC 7230 §3.2.2).");
+
+    pub static ref REQUEST_COOKIES: Test =
+        Test::new( "Fields 4"
+                 , "Request with two `Cookie` fields"
+                 , get("/fields/cookies", &["Cookie: a=1", "Cookie: b=2"])
+                 , expect(all_of(vec![
+                              request_fields("Cookie",
+                                  "the same cookies, joined with `; `",
+                                  |values: &[Vec<u8>]|
+                                      values.iter().all(|value|
+                                          !value.contains(&b',')) &&
+                                      items_are(&items(values, b';'),
+                                                &["a=1", "b=2"]))
+                            , complete_ok()
+                          ]),
+                          "Proxy must keep Cookie fields in order, and only \
+                           combine them with `; `"))
+            .with_upstream("/fields/cookies", ok)
+            .with_reference(6265, "5.4")
+            .with_tags(vec!["fields"])
+            .with_explanation(
+                "`Cookie` isn't a comma-separated list. If a proxy combines \
+                 two Cookie headers, RFC 6265 §5.4 says the values are joined \
+                 with `; `. Joining them with a comma makes the second cookie \
+                 part of the first cookie's value.");
+
+    pub static ref SET_COOKIE_NOT_MERGED: Test =
+        Test::new( "Fields 5"
+                 , "Response with two `Set-Cookie` fields"
+                 , get("/fields/set_cookie", &[])
+                 , expect(all_of(vec![
+                              complete_ok()
+                            , header_matches("Set-Cookie",
+                                  "both cookies, separately",
+                                  |values: &[Vec<u8>]| items_are(values, &[
+                                      "a=1; Expires=Wed, 21 Oct 2026 07:28:00 \
+                                       GMT; Path=/"
+                                    , "b=2; HttpOnly"
+                                  ]))
+                          ]),
+                          "Proxy must never combine Set-Cookie fields"))
+            .with_upstream("/fields/set_cookie", |_: &Received| {
+                Script::respond(Response::new()
+                    .header("Set-Cookie", "a=1; Expires=Wed, 21 Oct 2026 \
+                                           07:28:00 GMT; Path=/")
+                    .header("Set-Cookie", "b=2; HttpOnly")
+                    .body("cookies"))
+            })
+            .with_reference(6265, "3")
+            .with_tags(vec!["fields"])
+            .with_explanation(
+                "`Set-Cookie` values contain commas of their own, in \
+                 `Expires` dates, so two of them can't be combined into one \
+                 field without changing their meaning (RFC 6265 §3, RFC 7230 \
+                 §3.2.2). The client must get both, separately, in order.");
+
+    pub static ref RESPONSE_LIST_ORDER: Test =
+        Test::new( "Fields 6"
+                 , "Response with two `Link` fields"
+                 , get("/fields/response_list", &[])
+                 , expect(response_list("Link", &[
+                              "</a.css>; rel=preload"
+                            , "</b.js>; rel=preload"
+                            , "</c.png>; rel=preload"
+                          ]),
+                          "Proxy must keep the order of fields with the same \
+                           name, and only combine them with commas"))
+            .with_upstream("/fields/response_list", |_: &Received| {
+                Script::respond(Response::new()
+                    .header("Link", "</a.css>; rel=preload")
+                    .header("X-Flossy-Between", "1")
+                    .header("Link", "</b.js>; rel=preload, \
+                                     </c.png>; rel=preload")
+                    .body("links"))
+            })
+            .with_reference(7230, "3.2.2")
+            .with_tags(vec!["fields"])
+            .with_explanation(
+                "`Link` is a list, and the upstream sends it as two fields \
+                 with another field between them. The client must get the \
+                 links in the order the upstream sent them, whether in one \
+                 field or several (RFC 7230 §3.2.2).");
+
+    pub static ref RESPONSE_VALUE_BYTES: Test =
+        Test::new( "Fields 7"
+                 , "Response field value with mixed case, quotes and \
+                    `obs-text`"
+                 , get("/fields/response_bytes", &[])
+                 , expect(all_of(vec![
+                              complete_ok()
+                            , header_matches("X-Flossy-Bytes",
+                                  "the value exactly as sent",
+                                  |values: &[Vec<u8>]|
+                                      values.len() == 1 &&
+                                      trim(&values[0]) == BYTES)
+                          ]),
+                          "Proxy must forward field values exactly as sent"))
+            .with_upstream("/fields/response_bytes", |_: &Received| {
+                Script::respond(Response::new()
+                    .header("X-Flossy-Bytes", BYTES)
+                    .body("bytes"))
+            })
+            .with_reference(7230, "3.2")
+            .with_tags(vec!["fields"])
+            .with_explanation(
+                "As Fields 1, in the other direction. Every byte of the \
+                 upstream's field value must reach the client, including its \
+                 case, quotes and `obs-text` (RFC 7230 §3.2.4).");
+}
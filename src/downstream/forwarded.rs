@@ -0,0 +1,472 @@
+//! Tests for the headers that say who a request came from.
+//!
+//! Proxies pass on the client's address and protocol in `Forwarded`
+//! (RFC 7239), or in the older `X-Forwarded-For`, `X-Forwarded-Proto`
+//! and `X-Real-IP` headers. Upstreams use them for logging, rate limits
+//! and access control, so a client that can choose what they say can
+//! pretend to be anyone. Whether the proxy should keep the values a
+//! client sends depends on how it's deployed, so these tests follow the
+//! trust policy in the proxy's `Profile`: a proxy that trusts its
+//! clients must append to their values in order, and one that doesn't
+//! must throw them away. Either way, a `Forwarded` header has to follow
+//! RFC 7239's syntax, which means quoting IPv6 addresses (§6).
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use super::{Exchange, Outcome, Test, Trust, get, ok};
+use super::expect::{ Assertion, all_of, any_of, complete_ok, expect
+                   , forwarded, outcome, status_is, upstream_header };
+
+/// an address a client might claim to be, from TEST-NET-1
+const SPOOFED: &'static str = "192.0.2.60";
+
+/// the parameters of each element of a `Forwarded` header
+type Elements = Vec<Vec<(String, String)>>;
+
+/// returns true if `b` may appear in a token (RFC 7230 §3.2.6)
+fn is_tchar(b: u8) -> bool {
+    match b {
+        b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' => true
+      , b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'*' | b'+' | b'-'
+      | b'.' | b'^' | b'_' | b'`' | b'|' | b'~' => true
+      , _ => false
+    }
+}
+
+/// the index of the first byte from `i` on that isn't whitespace
+fn skip_ows(value: &[u8], mut i: usize) -> usize {
+    while i < value.len() && (value[i] == b' ' || value[i] == b'\t') {
+        i += 1;
+    }
+    i
+}
+
+/// the index just past the token starting at `i`
+fn token_end(value: &[u8], mut i: usize) -> usize {
+    while i < value.len() && is_tchar(value[i]) {
+        i += 1;
+    }
+    i
+}
+
+/// the contents of the quoted-string starting at `i`, and the index just
+/// past it
+fn quoted(value: &[u8], i: usize) -> Option<(Vec<u8>, usize)> {
+    let mut contents = Vec::new();
+    let mut j = i + 1;
+    while j < value.len() {
+        match value[j] {
+            b'"' => return Some((contents, j + 1))
+          , b'\\' if j + 1 < value.len() => {
+                contents.push(value[j + 1]);
+                j += 2;
+            }
+          , b'\t' | b' '...b'~' | 0x80...0xff => {
+                contents.push(value[j]);
+                j += 1;
+            }
+          , _ => return None
+        }
+    }
+    None
+}
+
+/// Parse a `Forwarded` header value into its elements, or return `None`
+/// if it isn't valid RFC 7239 syntax. Parameter names are lowercased,
+/// and quoted values unquoted.
+fn parse_forwarded(value: &[u8]) -> Option<Elements> {
+    let mut elements = Vec::new();
+    let mut element = Vec::new();
+    let mut i = skip_ows(value, 0);
+    while i < value.len() {
+        match value[i] {
+            b',' => {
+                elements.push(mem::replace(&mut element, Vec::new()));
+                i = skip_ows(value, i + 1);
+                continue
+            }
+          , b';' => { i += 1; continue }
+          , _ => {}
+        }
+        let name_end = token_end(value, i);
+        if name_end == i || value.get(name_end) != Some(&b'=') {
+            return None
+        }
+        let name = String::from_utf8_lossy(&value[i..name_end])
+            .to_lowercase();
+        let (param, end) = if value.get(name_end + 1) == Some(&b'"') {
+            match quoted(value, name_end + 1) {
+                Some(quoted) => quoted
+              , None => return None
+            }
+        } else {
+            let end = token_end(value, name_end + 1);
+            if end == name_end + 1 { return None }
+            (value[name_end + 1..end].to_vec(), end)
+        };
+        element.push((name, String::from_utf8_lossy(&param).into_owned()));
+
+        // pairs are separated by `;` with no whitespace, and elements by
+        // `,` with optional whitespace
+        i = skip_ows(value, end);
+        match value.get(i) {
+            None | Some(&b',') => {}
+          , Some(&b';') if i == end => {}
+          , Some(_) => return None
+        }
+    }
+    elements.push(element);
+    elements.retain(|element| !element.is_empty());
+    Some(elements)
+}
+
+/// returns true if `name` is an obfuscated identifier (RFC 7239 §6.3)
+fn is_obfuscated(name: &str) -> bool {
+    name.len() > 1 && name.starts_with('_') &&
+    name.bytes().all(|b| match b {
+        b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'.' | b'_' | b'-' => true
+      , _ => false
+    })
+}
+
+/// returns true if `node` is a valid `for` or `by` value (RFC 7239 §6)
+fn is_node(node: &str) -> bool {
+    let (name, port) = if node.starts_with('[') {
+        match node.find(']') {
+            Some(end) => node.split_at(end + 1)
+          , None => return false
+        }
+    } else {
+        match node.find(':') {
+            Some(colon) => node.split_at(colon)
+          , None => (node, "")
+        }
+    };
+    let name_ok = if name.starts_with('[') {
+        name[1..name.len() - 1].parse::<Ipv6Addr>().is_ok()
+    } else {
+        name.parse::<Ipv4Addr>().is_ok() || name == "unknown" ||
+        is_obfuscated(name)
+    };
+    let port_ok = port.is_empty() || port.starts_with(':') && {
+        let port = &port[1..];
+        (!port.is_empty() && port.len() <= 5 &&
+         port.chars().all(|c| c.is_digit(10))) || is_obfuscated(port)
+    };
+    name_ok && port_ok
+}
+
+/// returns true if `s` is an address an `X-Forwarded-For` list may hold
+fn is_address(s: &str) -> bool {
+    s.parse::<IpAddr>().is_ok() || s.parse::<SocketAddr>().is_ok()
+}
+
+/// every comma-separated item in the header values `values`, in order
+fn items(values: &[Vec<u8>]) -> Vec<String> {
+    values.iter()
+        .flat_map(|value| String::from_utf8_lossy(value)
+                              .split(',')
+                              .map(|item| item.trim().to_owned())
+                              .collect::<Vec<_>>())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// The elements of the `Forwarded` header values `values`, or `None` if
+/// any of them isn't valid RFC 7239 syntax, or has a `for` or `by`
+/// parameter that isn't a node.
+fn elements(values: &[Vec<u8>]) -> Option<Elements> {
+    let mut elements = Vec::new();
+    for value in values {
+        let parsed = match parse_forwarded(value) {
+            Some(parsed) => parsed
+          , None => return None
+        };
+        let bad_node = parsed.iter()
+            .flat_map(|element| element.iter())
+            .any(|&(ref name, ref node)|
+                (name == "for" || name == "by") && !is_node(node));
+        if bad_node { return None }
+        elements.extend(parsed);
+    }
+    Some(elements)
+}
+
+/// returns true if the proxy is meant to keep client-identity headers
+fn trusted(exchange: &Exchange) -> bool {
+    exchange.profile.trust == Trust::Clients
+}
+
+/// `if_trusted` if the proxy is meant to keep client-identity headers,
+/// and `otherwise` if it isn't
+fn by_trust(if_trusted: Assertion, otherwise: Assertion) -> Assertion {
+    Assertion::new(move |exchange: &Exchange| {
+        if trusted(exchange) { if_trusted.check(exchange) }
+        else { otherwise.check(exchange) }
+    })
+}
+
+/// `assertion`, unless the proxy is meant to keep client-identity
+/// headers
+fn unless_trusted(assertion: Assertion) -> Assertion {
+    Assertion::new(move |exchange: &Exchange| {
+        if trusted(exchange) { None } else { assertion.check(exchange) }
+    })
+}
+
+/// every `Forwarded` header the upstream recieved is valid
+fn valid_forwarded() -> Assertion {
+    upstream_header("Forwarded", "a Forwarded header in RFC 7239 syntax",
+        |values: &[Vec<u8>]| elements(values).is_some())
+}
+
+/// the upstream's `Forwarded` header starts with exactly `sent` if the
+/// proxy trusts its clients, and never mentions `node` if it doesn't
+fn forwarded_kept( sent: &'static [(&'static str, &'static str)]
+                 , node: &'static str) -> Assertion {
+    let kept = move |values: &[Vec<u8>]| {
+        let elements = elements(values).unwrap_or_default();
+        elements.first().map(|first| {
+            first.len() == sent.len() &&
+            first.iter().zip(sent).all(|(&(ref name, ref value), &(n, v))|
+                name == n && value == v)
+        }).unwrap_or(false)
+    };
+    let dropped = move |values: &[Vec<u8>]| {
+        !elements(values).unwrap_or_default().iter()
+            .flat_map(|element| element.iter())
+            .any(|&(_, ref value)| value.contains(node))
+    };
+    all_of(vec![ forwarded()
+               , valid_forwarded()
+               , by_trust(upstream_header("Forwarded",
+                              "the client's element first, unchanged", kept)
+                         , upstream_header("Forwarded",
+                              "no element from the client", dropped))
+               , complete_ok()
+               ])
+}
+
+/// the upstream's `X-Forwarded-For` list starts with `sent`, followed
+/// only by addresses, if the proxy trusts its clients, and has none of
+/// `sent` in it if it doesn't
+fn x_forwarded_for_kept(sent: &'static [&'static str]) -> Assertion {
+    let kept = move |values: &[Vec<u8>]| {
+        let received = items(values);
+        received.len() >= sent.len() &&
+        received.iter().zip(sent).all(|(r, s)| &r[..] == *s) &&
+        received[sent.len()..].iter().all(|r| is_address(r))
+    };
+    let dropped = move |values: &[Vec<u8>]| {
+        !items(values).iter().any(|r| sent.iter().any(|s| &r[..] == *s))
+    };
+    all_of(vec![ forwarded()
+               , by_trust(upstream_header("X-Forwarded-For",
+                              "the client's addresses first, then only \
+                               addresses", kept)
+                         , upstream_header("X-Forwarded-For",
+                              "none of the client's addresses", dropped))
+               , complete_ok()
+               ])
+}
+
+lazy_static! {
+    pub static ref FORWARDED_SYNTAX: Test =
+        Test::new( "Forwarded 1"
+                 , "Request with no client-identity headers"
+                 , get("/forwarded/syntax", &[])
+                 // a proxy doesn't have to add Forwarded at all, but if it
+                 // does, the header must be valid
+                 , expect(all_of(vec![ forwarded()
+                                     , valid_forwarded()
+                                     , complete_ok()
+                                     ]),
+                          "Forwarded header must follow RFC 7239 syntax"))
+            .with_upstream("/forwarded/syntax", ok)
+            .with_reference(7239, "4")
+            .with_tags(vec!["forwarded"])
+            .with_explanation(
+                "A proxy doesn't have to add a `Forwarded` header, but if it \
+                 does, the header must follow RFC 7239's syntax. That means \
+                 semicolon-separated pairs, comma-separated elements, and \
+                 `for` and `by` values that are IP addresses, `unknown` or \
+                 obfuscated identifiers, with IPv6 addresses bracketed and \
+                 quoted (§4, §6). Upstreams that can't parse the header may \
+                 fall back to trusting something worse.");
+
+    pub static ref FORWARDED_APPENDED: Test =
+        Test::new( "Forwarded 2"
+                 , "Request with a `Forwarded` header"
+                 , get("/forwarded/appended", &[
+                       "Forwarded: for=192.0.2.60;proto=http;by=203.0.113.43"
+                   ])
+                 , expect(forwarded_kept(&[ ("for", SPOOFED)
+                                          , ("proto", "http")
+                                          , ("by", "203.0.113.43")
+                                          ], SPOOFED),
+                          "Proxy must keep a trusted Forwarded element \
+                           unchanged, and drop an untrusted one"))
+            .with_upstream("/forwarded/appended", ok)
+            .with_reference(7239, "4")
+            .with_tags(vec!["forwarded", "security"])
+            .with_explanation(
+                "The client sends a `Forwarded` header of its own. A proxy \
+                 that trusts its clients, like one behind a load balancer, \
+                 must keep that element and append its own after it (RFC 7239 \
+                 §4). A proxy at the edge must throw it away, or anyone can \
+                 claim to be any address.\n\
+                 \n\
+                 Which one applies depends on how the proxy is deployed, so \
+                 this test follows `--trust-forwarded`.");
+
+    pub static ref FORWARDED_IPV6: Test =
+        Test::new( "Forwarded 3"
+                 , "Request with a `Forwarded` IPv6 address and port"
+                 , get("/forwarded/ipv6", &[
+                       "Forwarded: for=\"[2001:db8:cafe::17]:4711\""
+                   ])
+                 , expect(forwarded_kept( &[("for", "[2001:db8:cafe::17]:4711")]
+                                        , "2001:db8:cafe::17"),
+                          "Proxy must keep a trusted Forwarded IPv6 node \
+                           quoted and unchanged, and drop an untrusted one"))
+            .with_upstream("/forwarded/ipv6", ok)
+            .with_reference(7239, "6")
+            .with_tags(vec!["forwarded"])
+            .with_explanation(
+                "An IPv6 address with a port has to be quoted in `Forwarded`, \
+                 as `for=\"[2001:db8:cafe::17]:4711\"`, because colons and \
+                 brackets aren't allowed in a token (RFC 7239 §6). A trusting \
+                 proxy must keep the quotes. A proxy that doesn't trust its \
+                 clients must drop the element.");
+
+    pub static ref FORWARDED_MALFORMED: Test =
+        Test::new( "Forwarded 4"
+                 , "Request with a malformed `Forwarded` header"
+                 , get("/forwarded/malformed", &[
+                       "Forwarded: for=192.0.2.60;;by=\"unterminated"
+                   ])
+                 // appending to the client's value would leave the upstream
+                 // with a header it can't parse either
+                 , expect(any_of(vec![
+                              all_of(vec![ outcome(Outcome::NotForwarded)
+                                         , status_is(400)
+                                         ])
+                            , all_of(vec![ forwarded()
+                                         , valid_forwarded()
+                                         , complete_ok()
+                                         ])
+                          ]),
+                          "Proxy must either respond 400 Bad Request, or \
+                           forward a valid Forwarded header"))
+            .with_upstream("/forwarded/malformed", ok)
+            .with_reference(7239, "4")
+            .with_tags(vec!["forwarded", "parsing"])
+            .with_explanation(
+                "The client's `Forwarded` header has an unterminated \
+                 quoted-string. Appending to it produces a header the \
+                 upstream can't parse either. The proxy must drop or replace \
+                 it, or refuse the request, so that whatever reaches the \
+                 upstream is valid RFC 7239 syntax.");
+
+    pub static ref X_FORWARDED_FOR_APPENDED: Test =
+        Test::new( "Forwarded 5"
+                 , "Request with an `X-Forwarded-For` header"
+                 , get("/forwarded/xff", &["X-Forwarded-For: 192.0.2.60"])
+                 , expect(x_forwarded_for_kept(&[SPOOFED]),
+                          "Proxy must append to a trusted X-Forwarded-For in \
+                           order, and drop an untrusted one"))
+            .with_upstream("/forwarded/xff", ok)
+            .with_tags(vec!["forwarded", "security"])
+            .with_explanation(
+                "`X-Forwarded-For` isn't standardized, but it works like \
+                 `Forwarded`: each proxy appends the address it received the \
+                 request from. A proxy that trusts its clients must keep the \
+                 client's list and add to the end of it. One that doesn't \
+                 must discard it, or the upstream will believe whatever \
+                 address the client chose.\n\
+                 \n\
+                 This test follows `--trust-forwarded`.");
+
+    pub static ref X_FORWARDED_FOR_MULTIPLE: Test =
+        Test::new( "Forwarded 6"
+                 , "Request with two `X-Forwarded-For` headers"
+                 , get("/forwarded/xff_multiple", &[
+                       "X-Forwarded-For: 192.0.2.60"
+                     , "X-Forwarded-For: 198.51.100.17, 2001:db8::1"
+                   ])
+                 , expect(x_forwarded_for_kept(
+                              &[SPOOFED, "198.51.100.17", "2001:db8::1"]),
+                          "Proxy must append to trusted X-Forwarded-For \
+                           headers in order, and drop untrusted ones"))
+            .with_upstream("/forwarded/xff_multiple", ok)
+            .with_reference(7230, "3.2.2")
+            .with_tags(vec!["forwarded", "fields"])
+            .with_explanation(
+                "The client sends two `X-Forwarded-For` headers. Together \
+                 they form one list, in order (RFC 7230 §3.2.2). A trusting \
+                 proxy must keep the whole list in order before appending. A \
+                 proxy that only keeps the last header, or reverses the \
+                 order, changes which address the upstream thinks is the \
+                 client's.");
+
+    pub static ref X_FORWARDED_PROTO: Test =
+        Test::new( "Forwarded 7"
+                 , "Request over HTTP claiming `X-Forwarded-Proto: https`"
+                 , get("/forwarded/proto", &["X-Forwarded-Proto: https"])
+                 , expect(all_of(vec![
+                              forwarded()
+                            , by_trust(
+                                  upstream_header("X-Forwarded-Proto",
+                                      "the client's `https`, if any",
+                                      |values: &[Vec<u8>]| {
+                                          let proto = items(values);
+                                          proto.is_empty() ||
+                                          proto.iter().any(|p| p == "https")
+                                      })
+                                , upstream_header("X-Forwarded-Proto",
+                                      "no `https` from the client",
+                                      |values: &[Vec<u8>]|
+                                          !items(values).iter()
+                                              .any(|p| p == "https")))
+                            , complete_ok()
+                          ]),
+                          "Proxy must keep a trusted X-Forwarded-Proto, and \
+                           drop an untrusted one"))
+            .with_upstream("/forwarded/proto", ok)
+            .with_tags(vec!["forwarded", "security"])
+            .with_explanation(
+                "The client claims `X-Forwarded-Proto: https` on a plain HTTP \
+                 connection. An upstream that believes it may put session \
+                 cookies in responses sent in the clear, or skip redirecting \
+                 to HTTPS. A proxy that doesn't trust its clients must not \
+                 forward the claim.");
+
+    pub static ref X_REAL_IP: Test =
+        Test::new( "Forwarded 8"
+                 , "Request with an `X-Real-IP` header"
+                 , get("/forwarded/real_ip", &["X-Real-IP: 192.0.2.60"])
+                 , expect(all_of(vec![
+                              forwarded()
+                            , upstream_header("X-Real-IP", "a single address",
+                                  |values: &[Vec<u8>]| {
+                                      let real_ip = items(values);
+                                      real_ip.len() <= 1 &&
+                                      real_ip.iter().all(|ip| is_address(ip))
+                                  })
+                            , unless_trusted(upstream_header("X-Real-IP",
+                                  "no address from the client",
+                                  |values: &[Vec<u8>]|
+                                      !items(values).iter()
+                                          .any(|ip| ip == SPOOFED)))
+                            , complete_ok()
+                          ]),
+                          "X-Real-IP must be a single address, and not one \
+                           from a client the proxy doesn't trust"))
+            .with_upstream("/forwarded/real_ip", ok)
+            .with_tags(vec!["forwarded", "security"])
+            .with_explanation(
+                "`X-Real-IP` holds a single address, the client's. A proxy \
+                 that doesn't trust its clients must replace or remove the \
+                 one the client sent. Either way, the upstream must never see \
+                 more than one value.");
+}
@@ -0,0 +1,258 @@
+//! Tests for how the proxy handles the `Host` header.
+//!
+//! RFC 7230 §5.4 requires a server to answer an HTTP/1.1 request with no
+//! `Host` header, more than one, or one whose value isn't a valid
+//! `uri-host [ ":" port ]`, with 400 Bad Request. A valid host is still
+//! case-insensitive, and may end with the root label's dot. When the
+//! request-target is in absolute form, a proxy must ignore the `Host`
+//! header it recieved and send one naming the target's authority.
+//!
+//! Each test's request names its route in a `Flossy-Test` header as well
+//! as its path, so the upstream can tell which test it's for even if the
+//! proxy rewrites the request-target.
+use std::io::Result;
+
+use super::{Exchange, Kind, Outcome, RawRequest, Status, Test, ok};
+use super::expect::{ Assertion, Mismatch, all_of, complete_ok, expect
+                   , forwarded, observed_headers, outcome, status_is };
+
+/// build a `GET` request for `path` with one line for each of `hosts`,
+/// which are templates as in `RawRequest::with_template`
+fn request(path: &str, hosts: &[&str]) -> RawRequest {
+    let mut request = RawRequest::new();
+    request.with_line(format!("GET {} HTTP/1.1", path));
+    for host in hosts {
+        request.with_template(*host);
+    }
+    request.with_line(format!("Flossy-Test: {}", path))
+           .with_line("Connection: close")
+           .with_line("");
+    request
+}
+
+/// Returns a check that the proxy answered 400 without forwarding the
+/// request.
+fn rejected() -> impl Fn(&Exchange) -> Result<Status> + Sync {
+    expect( all_of(vec![outcome(Outcome::NotForwarded), status_is(400)])
+          , "Proxy must answer a request with an invalid Host with 400 Bad \
+             Request")
+}
+
+/// the proxy forwarded the request with a single `Host` header that
+/// `valid` accepts, which is described by `what`. a reverse proxy may
+/// also replace the `Host` with the authority of the upstream it's
+/// configured with.
+fn forwarded_host<F>(what: &'static str, valid: F) -> Assertion
+where F: Fn(&str) -> bool + Sync + 'static {
+    let host = Assertion::new(move |exchange: &Exchange| {
+        let request = match exchange.upstream.first() {
+            Some(request) => request
+          , None => return None
+        };
+        let hosts = request.headers_named("Host")
+                           .map(|value| value.to_vec())
+                           .collect::<Vec<_>>();
+        if hosts.len() == 1 {
+            let host = String::from_utf8_lossy(&hosts[0]);
+            let rewritten = exchange.profile.kind == Kind::Reverse &&
+                            host.eq_ignore_ascii_case(&exchange.authority);
+            if valid(&host) || rewritten { return None }
+        }
+        Some(Mismatch { expected: format!("the upstream to recieve {}", what)
+                      , actual: observed_headers("Host", &hosts)
+                      })
+    });
+    all_of(vec![forwarded(), host, complete_ok()])
+}
+
+/// returns true if `host` is the upstream's address with the host name
+/// `name`, ignoring case
+fn names(host: &str, name: &str) -> bool {
+    host.rsplitn(2, ':').last()
+        .map(|h| h.eq_ignore_ascii_case(name))
+        .unwrap_or(false)
+}
+
+lazy_static! {
+    pub static ref MISSING_HOST: Test =
+        Test::new( "Host 1"
+                 , "HTTP/1.1 request with no Host header"
+                 , request("/hosts/missing", &[])
+                 , rejected())
+            .with_upstream("/hosts/missing", ok)
+            .with_reference(7230, "5.4")
+            .with_tags(vec!["host", "security"])
+            .with_explanation(
+                "Every HTTP/1.1 request must have a Host header, and RFC 7230 \
+                 §5.4 says a server must respond with 400 Bad Request to one \
+                 that doesn't. A proxy that forwards the request anyway, or \
+                 fills in a Host of its own, leaves the upstream to guess \
+                 which site was meant.");
+
+    pub static ref MULTIPLE_HOSTS: Test =
+        Test::new( "Host 2"
+                 , "Request with two different Host headers"
+                 , request("/hosts/multiple", &[ "Host: {host}"
+                                               , "Host: example.com"])
+                 , rejected())
+            .with_upstream("/hosts/multiple", ok)
+            .with_reference(7230, "5.4")
+            .with_tags(vec!["host", "security"])
+            .with_explanation(
+                "The request has two Host headers naming different hosts. RFC \
+                 7230 §5.4 requires a 400 Bad Request. If the proxy routes by \
+                 the first and the upstream reads the second, a request the \
+                 proxy allowed for one site is served by another. This is the \
+                 basis of several cache poisoning and routing attacks.");
+
+    pub static ref REPEATED_HOST: Test =
+        Test::new( "Host 3"
+                 , "Request with the same Host header twice"
+                 , request("/hosts/repeated", &[ "Host: {host}"
+                                               , "Host: {host}"])
+                 , rejected())
+            .with_upstream("/hosts/repeated", ok)
+            .with_reference(7230, "5.4")
+            .with_tags(vec!["host"])
+            .with_explanation(
+                "The request has the same Host header twice. It's still more \
+                 than one Host header, which RFC 7230 §5.4 says must be \
+                 answered with 400 Bad Request, even if the copies agree.");
+
+    pub static ref INVALID_HOST_CHARS: Test =
+        Test::new( "Host 4"
+                 , "Host with characters that aren't allowed in a host name"
+                 , request( "/hosts/invalid_chars"
+                          , &["Host: local<host>:{port}"])
+                 , rejected())
+            .with_upstream("/hosts/invalid_chars", ok)
+            .with_reference(7230, "5.4")
+            .with_tags(vec!["host", "security"])
+            .with_explanation(
+                "The Host header contains `<` and `>`, which can't appear in \
+                 a host name. RFC 7230 §5.4 requires a 400 Bad Request for an \
+                 invalid Host. Upstreams that echo the Host into links or \
+                 redirects make an unvalidated one an easy route to \
+                 injection.");
+
+    pub static ref HOST_WITH_USERINFO: Test =
+        Test::new( "Host 5"
+                 , "Host with userinfo"
+                 , request("/hosts/userinfo", &["Host: user:secret@{host}"])
+                 , rejected())
+            .with_upstream("/hosts/userinfo", ok)
+            .with_reference(7230, "5.4")
+            .with_tags(vec!["host", "security"])
+            .with_explanation(
+                "The Host header has a `user:password@` prefix. Userinfo \
+                 isn't part of the Host syntax (RFC 7230 §5.4), and different \
+                 parsers disagree about which part of such a value is the \
+                 host. The proxy must reject it with 400 Bad Request.");
+
+    pub static ref HOST_PORT_OUT_OF_RANGE: Test =
+        Test::new( "Host 6"
+                 , "Host with a port number that's too large"
+                 , request("/hosts/port_range", &["Host: localhost:99999"])
+                 , rejected())
+            .with_upstream("/hosts/port_range", ok)
+            .with_reference(7230, "5.4")
+            .with_tags(vec!["host"])
+            .with_explanation(
+                "The Host header has port 99999, which no TCP port can be. A \
+                 proxy that truncates it, or wraps it modulo 65536, may route \
+                 the request somewhere the client never named. It must \
+                 respond with 400 Bad Request (RFC 7230 §5.4).");
+
+    pub static ref HOST_PORT_NOT_NUMERIC: Test =
+        Test::new( "Host 7"
+                 , "Host with a port that isn't a number"
+                 , request("/hosts/port_name", &["Host: localhost:http"])
+                 , rejected())
+            .with_upstream("/hosts/port_name", ok)
+            .with_reference(7230, "5.4")
+            .with_tags(vec!["host"])
+            .with_explanation(
+                "The Host header's port is `http` rather than a number. Ports \
+                 in a Host header are digits only (RFC 3986 §3.2.3), so this \
+                 is an invalid Host, which RFC 7230 §5.4 says must get a 400 \
+                 Bad Request.");
+
+    pub static ref HOST_IPV6_UNBRACKETED: Test =
+        Test::new( "Host 8"
+                 , "Host with an IPv6 literal that isn't in brackets"
+                 , request("/hosts/ipv6", &["Host: ::1:{port}"])
+                 , rejected())
+            .with_upstream("/hosts/ipv6", ok)
+            .with_reference(7230, "5.4")
+            .with_tags(vec!["host"])
+            .with_explanation(
+                "The Host header is an IPv6 literal without the brackets RFC \
+                 3986 requires, so there's no telling where the address ends \
+                 and the port begins. The proxy must reject it with 400 Bad \
+                 Request (RFC 7230 §5.4).");
+
+    pub static ref HOST_TRAILING_DOT: Test =
+        Test::new( "Host 9"
+                 , "Host name ending in the root label's dot"
+                 , request( "/hosts/trailing_dot"
+                          , &["Host: localhost.:{port}"])
+                 , expect(forwarded_host("a single Host naming localhost",
+                              |host| names(host, "localhost.") ||
+                                     names(host, "localhost")),
+                          "Proxy must forward the request with the same \
+                           Host"))
+            .with_upstream("/hosts/trailing_dot", ok)
+            .with_reference(3986, "3.2.2")
+            .with_tags(vec!["host"])
+            .with_explanation(
+                "`localhost.` is a fully-qualified name for the same host as \
+                 `localhost`, so the Host header is valid. The proxy must \
+                 forward the request with that host, with or without the dot, \
+                 rather than rejecting it. A reverse proxy may instead send \
+                 the Host of the upstream it's configured with.");
+
+    pub static ref HOST_UPPERCASE: Test =
+        Test::new( "Host 10"
+                 , "Host name in upper case"
+                 , request("/hosts/uppercase", &["Host: LOCALHOST:{port}"])
+                 , expect(forwarded_host("a single Host naming localhost",
+                              |host| names(host, "localhost")),
+                          "Proxy must forward the request with the same \
+                           Host"))
+            .with_upstream("/hosts/uppercase", ok)
+            .with_reference(3986, "3.2.2")
+            .with_tags(vec!["host"])
+            .with_explanation(
+                "Host names are case-insensitive (RFC 3986 §3.2.2), so \
+                 `LOCALHOST` is a valid Host that names the same host as \
+                 `localhost`. The proxy must forward the request rather than \
+                 rejecting it or treating it as a different site. A reverse \
+                 proxy may send the Host of the upstream it's configured with \
+                 instead.");
+
+    pub static ref HOST_ABSOLUTE_MISMATCH: Test = {
+        let mut request = RawRequest::new();
+        request.with_template("GET http://{host}/hosts/absolute HTTP/1.1")
+               .with_line("Host: example.com")
+               .with_line("Flossy-Test: /hosts/absolute")
+               .with_line("Connection: close")
+               .with_line("");
+        Test::new( "Host 11"
+                 , "Host that doesn't match an absolute-form request-target"
+                 , request
+                 , expect(forwarded_host("a single Host naming the target",
+                              |host| !names(host, "example.com")),
+                          "Proxy must replace the Host header with the \
+                           request-target's authority"))
+            .with_upstream("/hosts/absolute", ok)
+            .with_reference(7230, "5.4")
+            .with_tags(vec!["host", "security"])
+            .with_explanation(
+                "The request-target is in absolute form and names the \
+                 upstream, but the Host header says `example.com`. RFC 7230 \
+                 §5.4 says a proxy must ignore the Host it received in that \
+                 case, and send one taken from the request-target. Otherwise \
+                 the upstream would serve a site the proxy never checked the \
+                 request against.")
+    };
+}
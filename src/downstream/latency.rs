@@ -0,0 +1,162 @@
+//! Tests for how the proxy copes with a slow upstream.
+//!
+//! A slow upstream shouldn't change the response the client gets, as
+//! long as the proxy's timeouts haven't elapsed. When they do, the proxy
+//! should answer with 504 Gateway Timeout (RFC 7231 §6.6.5), and must not
+//! retry a non-idempotent request on its own (RFC 7230 §6.3.1), since
+//! the upstream may already have acted on it.
+use std::time::Duration;
+
+use upstream::{Received, Response, Script};
+
+use super::{Level, Request, Test, Verb};
+use super::expect::{ all_of, complete_ok, expect, status_is, streamed
+                   , upstream_requests };
+
+lazy_static! {
+    pub static ref DELAYED_STATUS_LINE: Test = {
+        let mut request = Request::new();
+        request.with_path("/latency/status_line")
+               .with_header("Connection: close");
+        Test::new( "Latency 1"
+                 , "Upstream waits before sending the status line"
+                 , request
+                 , expect(complete_ok(),
+                          "Proxy should wait for a slow upstream's status \
+                           line"))
+            .with_upstream("/latency/status_line", |_: &Received| {
+                Script::new()
+                    .delay(Duration::from_secs(2))
+                    .send(Response::new().body("hello"))
+            })
+            .with_reference(7230, "6.5")
+            .with_level(Level::Should)
+            .with_tags(vec!["latency", "timeouts"])
+            .with_explanation(
+                "The upstream waits two seconds before sending its response. \
+                 That's well within any reasonable timeout (RFC 7230 §6.5), \
+                 so the client should get the response exactly as if the \
+                 upstream had been fast.")
+    };
+
+    pub static ref DELAYED_HEADERS: Test = {
+        let mut request = Request::new();
+        request.with_path("/latency/headers")
+               .with_header("Connection: close");
+        Test::new( "Latency 2"
+                 , "Upstream waits between response headers"
+                 , request
+                 , expect(complete_ok(),
+                          "Proxy should wait for a slow upstream's headers"))
+            .with_upstream("/latency/headers", |_: &Received| {
+                Script::new()
+                    .send("HTTP/1.1 200 OK\r\n\
+                           X-Flossy: slow\r\n")
+                    .delay(Duration::from_secs(2))
+                    .send("Content-Length: 5\r\n\
+                           \r\n\
+                           hello")
+            })
+            .with_reference(7230, "6.5")
+            .with_level(Level::Should)
+            .with_tags(vec!["latency", "timeouts", "parsing"])
+            .with_explanation(
+                "The upstream sends the status line and one header, then \
+                 waits two seconds before sending the rest of the head. A \
+                 proxy whose parser expects the whole head in a single read \
+                 will fail or time out early here. It should wait for the \
+                 rest, within its timeouts (RFC 7230 §6.5).")
+    };
+
+    pub static ref DELAYED_CHUNKS: Test = {
+        let mut request = Request::new();
+        request.with_path("/latency/chunks")
+               .with_header("Connection: close");
+        Test::new( "Latency 3"
+                 , "Upstream waits between body chunks"
+                 , request
+                 // some of the body should have arrived before the upstream
+                 // sent its second chunk
+                 , expect(all_of(vec![ complete_ok()
+                                     , streamed(Duration::from_millis(2500))
+                                     ]),
+                          "Proxy should stream the response body rather than \
+                           buffering all of it"))
+            .with_upstream("/latency/chunks", |_: &Received| {
+                Script::new()
+                    .send(Response::new()
+                            .header("Transfer-Encoding", "chunked")
+                            .body("5\r\nhello\r\n"))
+                    .delay(Duration::from_secs(3))
+                    .send("5\r\nworld\r\n\
+                           0\r\n\
+                           \r\n")
+            })
+            .with_level(Level::Should)
+            .with_tags(vec!["latency", "streaming"])
+            .with_explanation(
+                "The upstream sends one chunk of its body, then waits three \
+                 seconds before sending the next. The client should get the \
+                 first chunk before the upstream sends the second. A proxy \
+                 that buffers whole responses makes every client wait for the \
+                 slowest upstream, and breaks anything that streams, like \
+                 server-sent events.")
+    };
+
+    pub static ref STALLED_POST_NOT_RETRIED: Test = {
+        let mut request = Request::new();
+        request.with_verb(Verb::Post)
+               .with_path("/latency/stalled_post")
+               .with_header("Content-Length: 5")
+               .with_header("Connection: close")
+               .with_body("hello");
+        Test::new( "Latency 4"
+                 , "Non-idempotent requests aren't retried after a timeout"
+                 , request
+                 , expect(upstream_requests("the request only once",
+                              |requests: &[Received]| requests.len() <= 1),
+                          "Proxy must not retry a POST request after the \
+                           upstream timed out"))
+            .with_upstream("/latency/stalled_post", |_: &Received| {
+                Script::new().stall()
+            })
+            // give the proxy's own timeout a chance to elapse
+            .with_timeout(Duration::from_secs(60))
+            .with_reference(7230, "6.3.1")
+            .with_tags(vec!["latency", "retries", "timeouts"])
+            .with_explanation(
+                "The upstream reads a POST and never answers. When the proxy \
+                 gives up, it must not send the POST again on its own, on the \
+                 same connection or a new one. The upstream may have acted on \
+                 it already, and RFC 7230 §6.3.1 forbids automatically \
+                 retrying a non-idempotent request.")
+    };
+
+    pub static ref STALLED_POST_TIMEOUT: Test = {
+        let mut request = Request::new();
+        request.with_verb(Verb::Post)
+               .with_path("/latency/stalled_post_timeout")
+               .with_header("Content-Length: 5")
+               .with_header("Connection: close")
+               .with_body("hello");
+        Test::new( "Latency 5"
+                 , "Timed-out non-idempotent requests get 504 Gateway Timeout"
+                 , request
+                 , expect(status_is(504),
+                          "Proxy should answer 504 Gateway Timeout when the \
+                           upstream times out on a POST request"))
+            .with_upstream("/latency/stalled_post_timeout", |_: &Received| {
+                Script::new().stall()
+            })
+            // give the proxy's own timeout a chance to elapse
+            .with_timeout(Duration::from_secs(60))
+            .with_reference(7231, "6.6.5")
+            .with_level(Level::Should)
+            .with_tags(vec!["latency", "timeouts"])
+            .with_explanation(
+                "The upstream reads a POST and never answers. Having given \
+                 up, the proxy should tell the client the upstream timed out \
+                 with 504 Gateway Timeout (RFC 7231 §6.6.5), rather than 502 \
+                 Bad Gateway or leaving the connection hanging.")
+    };
+}
@@ -0,0 +1,232 @@
+//! Tests for `OPTIONS`, `TRACE` and the `Max-Forwards` header.
+//!
+//! RFC 7231 §5.1.2 lets a client limit how far an `OPTIONS` or `TRACE`
+//! request travels: a proxy that recieves one with `Max-Forwards: 0`
+//! must answer it itself, and otherwise must forward it with the value
+//! decremented. A proxy answering `TRACE` reflects the request back to
+//! the client, but should leave out headers likely to hold credentials
+//! (RFC 7231 §4.3.8), which would otherwise be readable by scripts that
+//! can't read them directly. `TRACE` requests can't have a body.
+use upstream::{Received, Response, Script};
+
+use super::{Exchange, Level, Outcome, Request, Test, Verb};
+use super::expect::{ Assertion, Mismatch, all_of, complete_ok, expect
+                   , forwarded, outcome, status_is, upstream_header
+                   , upstream_requests };
+
+/// a credential that must never be reflected back to the client
+const SECRET: &'static str = "flossy-secret-credential";
+
+/// the upstream's `TRACE` behaviour: reflect the request it recieved
+fn echo(request: &Received) -> Script {
+    let mut message = request.method.clone();
+    message.push(b' ');
+    message.extend_from_slice(&request.target);
+    message.push(b' ');
+    message.extend_from_slice(&request.version);
+    message.extend_from_slice(b"\r\n");
+    for &(ref name, ref value) in &request.headers {
+        message.extend_from_slice(name.as_bytes());
+        message.extend_from_slice(b": ");
+        message.extend_from_slice(value);
+        message.extend_from_slice(b"\r\n");
+    }
+    message.extend_from_slice(b"\r\n");
+    Script::respond(Response::new()
+        .header("Content-Type", "message/http")
+        .body(message))
+}
+
+/// the proxy answered the request itself
+fn answered_by_proxy() -> Assertion {
+    all_of(vec![outcome(Outcome::NotForwarded), complete_ok()])
+}
+
+/// the proxy forwarded the request with `Max-Forwards: 0`
+fn forwarded_decremented() -> Assertion {
+    all_of(vec![ forwarded()
+               , upstream_header("Max-Forwards", "Max-Forwards: 0",
+                     |values: &[Vec<u8>]|
+                         values.len() == 1 && values[0] == b"0")
+               , complete_ok()
+               ])
+}
+
+/// the response doesn't reflect `SECRET`
+fn secret_kept() -> Assertion {
+    Assertion::new(|exchange: &Exchange| {
+        let response = String::from_utf8_lossy(&exchange.response);
+        if !response.contains(SECRET) { return None }
+        Some(Mismatch { expected: "no credentials in the response".to_owned()
+                      , actual: "the request's credentials".to_owned()
+                      })
+    })
+}
+
+/// returns a `TRACE` or `OPTIONS` request for `path`, with credentials
+fn request(verb: Verb, path: &str) -> Request {
+    let mut request = Request::new();
+    request.with_verb(verb)
+           .with_path(path)
+           .with_header(format!("Authorization: Bearer {}", SECRET))
+           .with_header(format!("Cookie: session={}", SECRET))
+           .with_header("Connection: close");
+    request
+}
+
+lazy_static! {
+    pub static ref OPTIONS_ASTERISK: Test = {
+        let mut request = Request::new();
+        request.with_verb(Verb::Options)
+               .with_path("*")
+               .with_header("Flossy-Test: max_forwards/options_asterisk")
+               .with_header("Connection: close");
+        Test::new( "Max-Forwards 1"
+                 , "`OPTIONS *` request"
+                 , request
+                 // the proxy may answer for the server itself, but if it
+                 // forwards the request, it must still ask about the server
+                 // rather than one of its resources
+                 , expect(all_of(vec![
+                              upstream_requests("an asterisk-form target",
+                                  |requests: &[Received]| requests.iter()
+                                      .all(|request| request.target == b"*"))
+                            , complete_ok()
+                          ]),
+                          "Proxy must forward `OPTIONS *` with an \
+                           asterisk-form target"))
+            .with_upstream("max_forwards/options_asterisk", |_: &Received| {
+                Script::respond(Response::new()
+                    .header("Allow", "GET, HEAD, OPTIONS"))
+            })
+            .with_reference(7230, "5.3.4")
+            .with_tags(vec!["max-forwards", "methods", "targets"])
+            .with_explanation(
+                "`OPTIONS *` asks about the server as a whole, not any of its \
+                 resources. The proxy may answer it itself. If it forwards \
+                 it, the request-target must still be `*` (RFC 7230 §5.3.4), \
+                 not `/` or anything else.")
+    };
+
+    pub static ref OPTIONS_MAX_FORWARDS_0: Test = {
+        let mut request = request(Verb::Options, "/max_forwards/options_0");
+        request.with_header("Max-Forwards: 0");
+        Test::new( "Max-Forwards 2"
+                 , "`OPTIONS` with Max-Forwards: 0"
+                 , request
+                 , expect(answered_by_proxy(),
+                          "Proxy must answer a request with Max-Forwards: 0 \
+                           itself"))
+            .with_upstream("/max_forwards/options_0", |_: &Received| {
+                Script::respond(Response::new())
+            })
+            .with_reference(7231, "5.1.2")
+            .with_tags(vec!["max-forwards", "methods"])
+            .with_explanation(
+                "An `OPTIONS` request with `Max-Forwards: 0` is addressed to \
+                 the proxy itself. RFC 7231 §5.1.2 says the proxy must not \
+                 forward it, and must answer it as the final recipient.")
+    };
+
+    pub static ref OPTIONS_MAX_FORWARDS_1: Test = {
+        let mut request = request(Verb::Options, "/max_forwards/options_1");
+        request.with_header("Max-Forwards: 1");
+        Test::new( "Max-Forwards 3"
+                 , "`OPTIONS` with Max-Forwards: 1"
+                 , request
+                 , expect(forwarded_decremented(),
+                          "Proxy must decrement Max-Forwards when \
+                           forwarding"))
+            .with_upstream("/max_forwards/options_1", |_: &Received| {
+                Script::respond(Response::new())
+            })
+            .with_reference(7231, "5.1.2")
+            .with_tags(vec!["max-forwards", "methods"])
+            .with_explanation(
+                "An `OPTIONS` request with `Max-Forwards: 1` may travel one \
+                 more hop. RFC 7231 §5.1.2 says the proxy must forward it \
+                 with `Max-Forwards: 0`.")
+    };
+
+    pub static ref TRACE_MAX_FORWARDS_0: Test = {
+        let mut request = request(Verb::Trace, "/max_forwards/trace_0");
+        request.with_header("Max-Forwards: 0");
+        Test::new( "Max-Forwards 4"
+                 , "`TRACE` with Max-Forwards: 0"
+                 , request
+                 , expect(answered_by_proxy(),
+                          "Proxy must answer a request with Max-Forwards: 0 \
+                           itself"))
+            .with_upstream("/max_forwards/trace_0", echo)
+            .with_reference(7231, "5.1.2")
+            .with_tags(vec!["max-forwards", "methods"])
+            .with_explanation(
+                "A `TRACE` request with `Max-Forwards: 0` is addressed to the \
+                 proxy itself. RFC 7231 §5.1.2 says the proxy must not \
+                 forward it, and must answer it as the final recipient by \
+                 reflecting the request back.")
+    };
+
+    pub static ref TRACE_CREDENTIALS: Test = {
+        let mut request = request(Verb::Trace, "/max_forwards/credentials");
+        request.with_header("Max-Forwards: 0");
+        Test::new( "Max-Forwards 5"
+                 , "Credentials in a `TRACE` the proxy answers"
+                 , request
+                 , expect(secret_kept(),
+                          "Proxy should not reflect credentials in its \
+                           answer to `TRACE`"))
+            .with_upstream("/max_forwards/credentials", echo)
+            .with_reference(7231, "4.3.8")
+            .with_level(Level::Should)
+            .with_tags(vec!["max-forwards", "methods", "security"])
+            .with_explanation(
+                "The proxy answers a `TRACE` request with `Max-Forwards: 0` \
+                 by reflecting the request back. RFC 7231 §4.3.8 says the \
+                 answer should leave out headers likely to hold credentials, \
+                 like `Authorization` and `Cookie`. Otherwise cross-site \
+                 tracing attacks can read credentials that scripts aren't \
+                 allowed to see.")
+    };
+
+    pub static ref TRACE_MAX_FORWARDS_1: Test = {
+        let mut request = request(Verb::Trace, "/max_forwards/trace_1");
+        request.with_header("Max-Forwards: 1");
+        Test::new( "Max-Forwards 6"
+                 , "`TRACE` with Max-Forwards: 1"
+                 , request
+                 , expect(forwarded_decremented(),
+                          "Proxy must decrement Max-Forwards when \
+                           forwarding"))
+            .with_upstream("/max_forwards/trace_1", echo)
+            .with_reference(7231, "5.1.2")
+            .with_tags(vec!["max-forwards", "methods"])
+            .with_explanation(
+                "A `TRACE` request with `Max-Forwards: 1` must be forwarded \
+                 with `Max-Forwards: 0` (RFC 7231 §5.1.2).")
+    };
+
+    pub static ref TRACE_WITH_BODY: Test = {
+        let mut request = request(Verb::Trace, "/max_forwards/trace_body");
+        request.with_header("Content-Length: 5")
+               .with_body("hello");
+        Test::new( "Max-Forwards 7"
+                 , "`TRACE` with a body"
+                 , request
+                 , expect(all_of(vec![ outcome(Outcome::NotForwarded)
+                                     , status_is(400)
+                                     ]),
+                          "Proxy should answer a TRACE request with a body \
+                           with 400 Bad Request"))
+            .with_upstream("/max_forwards/trace_body", echo)
+            .with_reference(7231, "4.3.8")
+            .with_level(Level::Should)
+            .with_tags(vec!["max-forwards", "methods", "framing"])
+            .with_explanation(
+                "RFC 7231 §4.3.8 says a client must not send a body in a \
+                 `TRACE` request. A proxy that forwards one sends the \
+                 upstream a message it may not expect to have a body, and may \
+                 frame it differently. The proxy should refuse it with 400 \
+                 Bad Request.")
+    };
+}
@@ -0,0 +1,127 @@
+//! What each test checks, and why.
+//!
+//! A test's name and one-line description say what it sends, but not
+//! which part of which RFC it holds the proxy to, how strictly, or why
+//! anyone should care. Tests carry that here, so `flossy explain` can
+//! show it, a la `rustc --explain`, and `flossy list --tag` can pick out
+//! every test about one subject.
+use std::fmt;
+
+use console::style;
+
+use super::Test;
+
+/// How strongly an RFC asks for the behaviour a test checks, in the
+/// terms of RFC 2119.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Level {
+    Must
+  , Should
+  , May
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match *self {
+            Level::Must => "MUST"
+          , Level::Should => "SHOULD"
+          , Level::May => "MAY"
+        })
+    }
+}
+
+impl Default for Level {
+    #[inline] fn default() -> Self { Level::Must }
+}
+
+/// The section of an RFC that a test checks.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Reference {
+    pub rfc: u16
+  , /// the section number, like `3.3.3`
+    pub section: &'static str
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RFC {} §{}", self.rfc, self.section)
+    }
+}
+
+/// the reference and level of `test`, like `RFC 7230 §3.3.3, MUST`
+fn requirement(test: &Test) -> String {
+    match test.reference() {
+        Some(reference) => format!("{}, {}", reference, test.level())
+      , None => test.level().to_string()
+    }
+}
+
+/// lowercase `name`, and treat `-` and `_` as spaces, so that
+/// `bad-framing-1` finds "Bad Framing 1"
+fn normalize(name: &str) -> String {
+    name.trim()
+        .to_lowercase()
+        .chars()
+        .map(|c| if c == '-' || c == '_' { ' ' } else { c })
+        .collect()
+}
+
+/// break `text` into lines no more than `width` columns wide, each
+/// starting with `indent`
+fn wrap(text: &str, width: usize, indent: &str) -> String {
+    let mut wrapped = String::new();
+    for paragraph in text.split("\n\n") {
+        if !wrapped.is_empty() { wrapped.push('\n') }
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            if !line.is_empty() && line.len() + word.len() + 1 > width {
+                wrapped.push_str(indent);
+                wrapped.push_str(&line);
+                wrapped.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() { line.push(' ') }
+            line.push_str(word);
+        }
+        if !line.is_empty() {
+            wrapped.push_str(indent);
+            wrapped.push_str(&line);
+            wrapped.push('\n');
+        }
+    }
+    wrapped
+}
+
+/// Returns the test in `tests` called `name`, ignoring case, and
+/// treating `-` and `_` as spaces.
+pub fn find_test(tests: &[&'static Test], name: &str)
+                 -> Option<&'static Test> {
+    let name = normalize(name);
+    tests.iter().find(|test| normalize(test.name) == name).cloned()
+}
+
+/// Print every test in `tests` that has all of `tags`.
+pub fn list_tests(tests: &[&'static Test], tags: &[&str]) {
+    let tagged = tests.iter()
+        .filter(|test| tags.iter().all(|&tag| test.has_tag(tag)));
+    for test in tagged {
+        println!("{}: {}", style(test.name).bold(), test.description);
+        println!("    {} [{}]", requirement(test), test.tags().join(", "));
+    }
+}
+
+/// Print everything there is to know about `test`.
+pub fn explain_test(test: &Test) {
+    println!( "{}: {}"
+            , style(test.name).bold(), style(test.description).bold());
+    println!("{}", requirement(test));
+    if let Some((kind, why)) = test.only() {
+        println!("Only applies to {} proxies: {}", kind, why);
+    }
+    if !test.tags().is_empty() {
+        println!("Tags: {}", test.tags().join(", "));
+    }
+    if !test.explanation().is_empty() {
+        print!("\n{}", wrap(test.explanation(), 72, ""));
+    }
+}
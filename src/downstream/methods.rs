@@ -0,0 +1,120 @@
+//! Tests for request method handling.
+//!
+//! RFC 7230 §3.1.1 makes the method token case-sensitive, and RFC 7231
+//! §4.1 expects intermediaries to forward methods they don't recognise
+//! rather than rejecting them. A request line whose method isn't a valid
+//! token at all is malformed: the proxy should answer it with 400, or
+//! close the connection, but never forward it.
+use upstream::{Received, Response, Script};
+
+use super::{Exchange, Level, Outcome, Request, Test, Verb};
+use super::checks::one_of;
+use super::expect::{all_of, expect, status_is, upstream_received};
+
+lazy_static! {
+    pub static ref LOWERCASE_METHOD: Test = {
+        let mut request = Request::new();
+        request.with_verb(Verb::custom("get"))
+               .with_path("/methods/lowercase")
+               .with_header("Connection: close");
+        Test::new( "Methods 1"
+                 , "Method names are case-sensitive"
+                 , request
+                 , expect(all_of(vec![
+                              upstream_received("a `get` request",
+                                  |request: &Received| request.method == b"get")
+                            , status_is(200)
+                          ]),
+                          "Proxy must not change the case of the request \
+                           method"))
+            .with_upstream("/methods/lowercase", |request: &Received| {
+                if request.method == b"get" {
+                    info!("Request was handled successfully");
+                    Script::respond(Response::new())
+                } else {
+                    info!("Method was rewritten to {:?}",
+                          String::from_utf8_lossy(&request.method));
+                    Script::respond(Response::new()
+                        .status_code(400, "Bad Request")
+                        .body("Proxy must not change the case of the \
+                               request method!"))
+                }
+            })
+            .with_reference(7230, "3.1.1")
+            .with_tags(vec!["methods"])
+            .with_explanation(
+                "Method names are case-sensitive (RFC 7230 §3.1.1), so `get` \
+                 is not `GET`; it's an extension method that happens to look \
+                 like it. A proxy that uppercases the method changes what the \
+                 request means, and may let a request through a filter that \
+                 would have stopped the real method.")
+    };
+
+    pub static ref UNKNOWN_METHOD: Test = {
+        let mut request = Request::new();
+        request.with_verb(Verb::custom("FLOSS"))
+               .with_path("/methods/extension")
+               .with_header("Connection: close");
+        Test::new( "Methods 2"
+                 , "Unknown extension methods are forwarded"
+                 , request
+                 , expect(all_of(vec![
+                              upstream_received("a `FLOSS` request",
+                                  |request: &Received|
+                                      request.method == b"FLOSS")
+                            , status_is(200)
+                          ]),
+                          "Proxy must forward extension methods unchanged"))
+            .with_upstream("/methods/extension", |request: &Received| {
+                if request.method == b"FLOSS" {
+                    info!("Request was handled successfully");
+                    Script::respond(Response::new())
+                } else {
+                    info!("Method was rewritten to {:?}",
+                          String::from_utf8_lossy(&request.method));
+                    Script::respond(Response::new()
+                        .status_code(400, "Bad Request")
+                        .body("Proxy must forward extension methods \
+                               unchanged!"))
+                }
+            })
+            .with_reference(7231, "4.1")
+            .with_level(Level::Should)
+            .with_tags(vec!["methods"])
+            .with_explanation(
+                "New methods are defined all the time, and HTTP lets them \
+                 through any intermediary that doesn't understand them (RFC \
+                 7231 §4.1). A proxy should forward an extension method like \
+                 `FLOSS` exactly as it received it, and leave it to the \
+                 origin server to answer 501 Not Implemented if it has to.")
+    };
+
+    pub static ref INVALID_METHOD: Test = {
+        let mut request = Request::new();
+        // parentheses are delimiters, so this isn't a valid token
+        request.with_verb(Verb::custom("G(E)T"))
+               .with_path("/methods/invalid")
+               .with_header("Connection: close");
+        Test::new( "Methods 3"
+                 , "Invalid method tokens are rejected"
+                 , request
+                 , |exchange: &Exchange| one_of(exchange,
+                        &[Outcome::Status(400), Outcome::Closed],
+                        "Proxy must reject a request with an invalid method"))
+            .with_upstream("/methods/invalid", |_: &Received| {
+                info!("Request should not have been recieved.");
+                Script::respond(Response::new()
+                    .body("This shouldn't have happened!"))
+            })
+            .with_reference(7230, "3.1.1")
+            .with_level(Level::Should)
+            .with_tags(vec!["methods", "parsing"])
+            .with_explanation(
+                "A method has to be a token, and parentheses are delimiters, \
+                 so `G(E)T` can't be one. RFC 7230 §3.1.1 says a recipient of \
+                 an invalid request-line should respond with 400 Bad Request. \
+                 A proxy that forwards it anyway leaves the upstream to parse \
+                 something the proxy couldn't, and they may not agree on what \
+                 it says.")
+    };
+}
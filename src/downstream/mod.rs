@@ -1,28 +1,85 @@
 use tokio_core::net::TcpStream;
-use tokio_core::reactor::Core;
-use tokio_io::io;
-use net2::TcpBuilder;
+use tokio_core::reactor::{Core, Handle};
 use futures::future::{self, Future};
 
-use std::io::{Error, ErrorKind, Result};
-use std::{fmt, str};
+use std::io::{Error, Result};
+use std::{fmt, ops, thread};
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 
-use httparse::{EMPTY_HEADER, Response};
+use upstream::{self, Handler, Received, Response, Script};
 
 use indicatif::{ProgressBar, ProgressStyle};
 use console::{Emoji, StyledObject, style};
 
 mod request;
 pub use self::request::*;
+mod delivery;
+pub use self::delivery::*;
+mod profile;
+pub use self::profile::*;
+mod exchange;
+pub use self::exchange::*;
+mod outcome;
+pub use self::outcome::*;
+mod methods;
+pub use self::methods::*;
+mod failures;
+pub use self::failures::*;
+mod latency;
+pub use self::latency::*;
+mod retries;
+pub use self::retries::*;
+mod connections;
+pub use self::connections::*;
+mod shutdown;
+pub use self::shutdown::*;
+mod bodies;
+pub use self::bodies::*;
+mod hosts;
+pub use self::hosts::*;
+mod targets;
+pub use self::targets::*;
+mod max_forwards;
+pub use self::max_forwards::*;
+mod caching;
+pub use self::caching::*;
+mod conditionals;
+pub use self::conditionals::*;
+mod ranges;
+pub use self::ranges::*;
+mod encodings;
+pub use self::encodings::*;
+mod forwarded;
+pub use self::forwarded::*;
+mod fields;
+pub use self::fields::*;
+mod meta;
+pub use self::meta::*;
+mod render;
+pub use self::render::*;
+mod probe;
+pub use self::probe::*;
+mod checks;
+use self::checks::one_of;
+mod expect;
+use self::expect::{all_of, expect, status_is, upstream_received};
 #[cfg(test)] mod test;
 
+/// Run `tests` against the proxy, displaying the results.
+///
+/// If a `delivery` is given, every test sends its request that way,
+/// rather than using the test's own delivery strategy. Checks that
+/// depend on how the proxy is deployed follow `profile`, and tests that
+/// don't apply to it are skipped.
 pub fn do_tests<'a>(upstream_uri: &'a str, proxy_addr: &SocketAddr,
-                    tests: &[&'static Test]) {
+                    tests: &[&'static Test], delivery: Option<&Delivery>,
+                    profile: &Profile, rendering: Rendering) -> Summary {
 
     // iterator of test results
     let results = tests.iter()
-        .map(|test| test.run(upstream_uri, proxy_addr));
+        .map(|test| test.run(upstream_uri, proxy_addr, delivery, profile));
 
     // create the progress bar, style it, and attach it to the
     // test results iterator
@@ -34,41 +91,104 @@ pub fn do_tests<'a>(upstream_uri: &'a str, proxy_addr: &SocketAddr,
 
     // collect the iterator into vectors of successes and failures
     // (this is where the tests actually are run)
-    let (successes, failures): (Vec<TestResult>, Vec<TestResult>) =
+    let (successes, others): (Vec<TestResult>, Vec<TestResult>) =
         results.partition(TestResult::is_passed);
+    let (skipped, others): (Vec<TestResult>, Vec<TestResult>) =
+        others.into_iter().partition(TestResult::is_skipped);
+    let (warnings, failures): (Vec<TestResult>, Vec<TestResult>) =
+        others.into_iter().partition(TestResult::is_warning);
 
     // display results
-    let summary =
-        format!( "{} successes, {} failures"
-                , successes.len(), failures.len());
-    progress.finish_with_message(&summary);
+    let summary = Summary { passed: successes.len()
+                          , warnings: warnings.len()
+                          , failed: failures.len()
+                          , skipped: skipped.len()
+                          };
+    progress.finish_with_message(&summary.to_string());
+
+    let show = |result: TestResult| match rendering {
+        Rendering::Escaped => format!("{}", result)
+      , Rendering::Hexdump => format!("{:#}", result)
+    };
 
     for success in successes {
-        println!("{}", style(success).green())
+        println!("{}", style(show(success)).green())
+    }
+
+    for skip in skipped {
+        println!("{}", style(show(skip)).dim())
+    }
+
+    for warning in warnings {
+        println!("{}", style(show(warning)).yellow())
     }
 
     for failure in failures {
-        println!("{}", style(failure).red())
+        println!("{}", style(show(failure)).red())
     }
 
+    summary
+}
+
+/// How many tests ended each way.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub passed: usize
+  , /// tests of recommendations the proxy didn't follow
+    pub warnings: usize
+  , /// tests the proxy failed, or that couldn't be run
+    pub failed: usize
+  , /// tests that don't apply to the proxy under test
+    pub skipped: usize
+}
+
+impl Summary {
+    /// Returns true if the proxy did everything the RFCs require of it.
+    #[inline] pub fn is_success(&self) -> bool { self.failed == 0 }
+}
+
+impl ops::AddAssign for Summary {
+    fn add_assign(&mut self, other: Summary) {
+        self.passed += other.passed;
+        self.warnings += other.warnings;
+        self.failed += other.failed;
+        self.skipped += other.skipped;
+    }
+}
 
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!( f, "{} successes, {} warnings, {} failures, {} skipped"
+              , self.passed, self.warnings, self.failed, self.skipped)
+    }
 }
 
 #[derive(Debug)]
 pub struct TestResult {
     pub name: &'static str
   , pub description: &'static str
+  , /// the delivery strategy the test was run with
+    pub delivery: Delivery
   , pub status: Result<Status>
 }
 
 impl fmt::Display for TestResult {
+    /// the alternate form, `{:#}`, shows the bytes in a failure as a
+    /// hexdump
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!( f, "{emoji} {name}: {desc}\n{status:<4}"
+        let delivery = if self.delivery == Delivery::whole() {
+            String::new()
+        } else {
+            format!(" (delivered {})", self.delivery)
+        };
+        write!( f, "{emoji} {name}: {desc}{delivery}\n{status:<4}"
               , emoji = self.emoji()
               , name = style(self.name).bold()
               , desc = style(self.description).bold()
+              , delivery = delivery
               , status = self.status.as_ref()
-                             .map(|s| format!("{}", s))
+                             .map(|s| if f.alternate() { format!("{:#}", s) }
+                                      else { format!("{}", s) })
                              .unwrap_or_else(|e| format!("{}", e))
                              .lines()
                              .map(|s| format!("  {}\n", s))
@@ -80,14 +200,31 @@ impl fmt::Display for TestResult {
 impl TestResult {
     pub fn is_passed(&self) -> bool {
         match self.status {
-            Ok(Status::Passed) => true
+            Ok(Status::Passed) | Ok(Status::Accepted(_)) => true
+          , _ => false
+        }
+    }
+
+    pub fn is_warning(&self) -> bool {
+        match self.status {
+            Ok(Status::Warning(_)) => true
+          , _ => false
+        }
+    }
+
+    pub fn is_skipped(&self) -> bool {
+        match self.status {
+            Ok(Status::Skipped { .. }) => true
           , _ => false
         }
     }
 
     pub fn emoji(&self) -> StyledObject<Emoji> {
         match self.status {
-            Ok(Status::Passed) => style(Emoji("✔️", "+")).green()
+            Ok(Status::Passed) | Ok(Status::Accepted(_)) =>
+                style(Emoji("✔️", "+")).green()
+          , Ok(Status::Warning(_)) => style(Emoji("⚠️", "~")).yellow()
+          , Ok(Status::Skipped { .. }) => style(Emoji("➖", "-")).dim()
           , Ok(_) => style(Emoji("✖️", "x")).red()
           , Err(_) => style(Emoji("❗", "!")).red().dim()
         }
@@ -96,41 +233,124 @@ impl TestResult {
 
 #[derive(Debug)]
 pub enum Status { Passed
+                , /// the proxy passed by doing one of several things the
+                  /// test accepts
+                  Accepted(Outcome)
                 , Failed { why: &'static str, bytes: Vec<u8> }
-                , FailedMessage { idx: usize, text: String }
+                , /// the proxy did something other than what the test
+                  /// expected
+                  Unexpected { why: &'static str
+                             , expected: String
+                             , actual: String
+                             , bytes: Vec<u8>
+                             }
+                , /// the proxy failed a test of something the RFCs only
+                  /// recommend, so the failure isn't held against it
+                  Warning(Box<Status>)
+                , /// the test doesn't apply to the proxy under test
+                  Skipped { reason: String }
                 }
 
+impl Status {
+    /// report a failure as only a warning; anything else is unchanged.
+    pub fn warning(self) -> Self {
+        match self {
+            failed @ Status::Failed { .. }
+          | failed @ Status::Unexpected { .. } =>
+                Status::Warning(Box::new(failed))
+          , status => status
+        }
+    }
+}
+
 impl fmt::Display for Status {
+    /// the alternate form, `{:#}`, shows the bytes the proxy sent as a
+    /// hexdump rather than escaped text
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rendering = if f.alternate() { Rendering::Hexdump }
+                        else { Rendering::Escaped };
         match *self {
             Status::Failed { ref why, ref bytes } =>
                 write!( f, "{why}\nRecieved instead:\n\n{response}"
                       , why = why
-                      , response = unsafe { str::from_utf8_unchecked(&bytes) }
+                      , response = render_response(bytes, rendering)
+                    )
+          , Status::Unexpected { why, ref expected, ref actual, ref bytes } =>
+                write!( f, "{why}\nExpected: {expected}\nObserved: {actual}\n\
+                            Recieved:\n\n{response}"
+                      , why = why
+                      , expected = expected
+                      , actual = actual
+                      , response = render_response(bytes, rendering)
                     )
-          , Status::FailedMessage { idx, ref text } =>
-              write!( f, "{why}\nRecieved instead:\n\n{response}"
-                    , why = &text[idx..]
-                    , response = text
-                  )
+          , Status::Accepted(outcome) => write!(f, "Observed: {}", outcome)
+          , Status::Warning(ref failed) if f.alternate() =>
+                write!(f, "{:#}", failed)
+          , Status::Warning(ref failed) => write!(f, "{}", failed)
+          , Status::Skipped { ref reason } =>
+                write!(f, "Skipped: {}", reason)
           , Status::Passed => write!(f, "")
         }
     }
 }
 
-type Check = (Fn(Vec<u8>) -> Result<Status>) + Sync;
+/// returns a `GET` request for `path` with the header lines `headers`
+fn get(path: &str, headers: &[&str]) -> Request {
+    let mut request = Request::new();
+    request.with_path(path);
+    for header in headers {
+        request.with_header(*header);
+    }
+    request.with_header("Connection: close");
+    request
+}
+
+/// an upstream handler that answers every request with a plain 200
+fn ok(_: &Received) -> Script {
+    Script::respond(Response::new().body("forwarded"))
+}
+
+type Check = (Fn(&Exchange) -> Result<Status>) + Sync;
 
-// TODO: can we add in-depth descriptions to these tests as well, a la
-//      `rustc --explain`?
-//          - eliza, 07/2/2017
 pub struct Test {
     /// the name of the test
     pub name: &'static str
   , /// a longer string describing the test
     pub description: &'static str
-  , /// function to generate the HTTP request that this test will
-    /// send to the proxy
-    request: Request<'static>
+  , /// the RFC section the test checks
+    reference: Option<Reference>
+  , /// how strongly the RFCs ask for what the test checks
+    level: Level
+  , /// the subjects the test is about, like `framing` or `security`
+    tags: Vec<&'static str>
+  , /// an in-depth explanation of the test, for `flossy explain`
+    explanation: &'static str
+  , /// the only kind of proxy the test applies to, and why, if it
+    /// doesn't apply to every kind
+    only: Option<(Kind, &'static str)>
+  , /// the HTTP request that this test will send to the proxy
+    request: RawRequest
+  , /// requests sent before this test's request, each on its own
+    /// connection
+    setup: Vec<RawRequest>
+  , /// how long to wait between the setup requests and this test's
+    /// request
+    pause: Option<Duration>
+  , /// how the request is written to the proxy
+    delivery: Delivery
+  , /// the most segments the request is written in, whatever delivery
+    /// strategy it's run under
+    segment_limit: Option<usize>
+  , /// what to do with the connection after writing the request
+    finish: Finish
+  , /// the test ID the upstream serves this test on, and how it
+    /// responds
+    upstream: Option<(&'static str, Arc<Handler>)>
+  , /// whether the request names an upstream address that refuses
+    /// connections, rather than the real upstream
+    unreachable: bool
+  , /// how long to wait for the proxy to finish responding
+    timeout: Duration
   , /// function to check whether the HTTP response returned by the
     /// proxy is correct
     check: Box<Check>
@@ -138,27 +358,172 @@ pub struct Test {
 
 impl Test {
 
+    pub fn new<R, C>(name: &'static str, description: &'static str,
+                     request: R, check: C) -> Self
+    where R: Into<RawRequest>
+        , C: Fn(&Exchange) -> Result<Status> + Sync + 'static {
+        Test { name: name
+             , description: description
+             , reference: None
+             , level: Level::default()
+             , tags: Vec::new()
+             , explanation: ""
+             , only: None
+             , request: request.into()
+             , setup: Vec::new()
+             , pause: None
+             , delivery: Delivery::default()
+             , segment_limit: None
+             , finish: Finish::default()
+             , upstream: None
+             , unreachable: false
+             , timeout: Duration::from_secs(10)
+             , check: Box::new(check)
+             }
+    }
+
+    /// record that this test checks section `section` of RFC `rfc`.
+    pub fn with_reference(mut self, rfc: u16, section: &'static str)
+                          -> Self {
+        self.reference = Some(Reference { rfc: rfc, section: section });
+        self
+    }
+
+    /// record how strongly the behaviour this test checks is required,
+    /// rather than `MUST`.
+    pub fn with_level(mut self, level: Level) -> Self {
+        self.level = level; self
+    }
+
+    /// tag this test with the subjects it's about.
+    pub fn with_tags(mut self, tags: Vec<&'static str>) -> Self {
+        self.tags.extend(tags); self
+    }
+
+    /// explain in depth what this test checks, and why it matters.
+    /// paragraphs are separated by blank lines.
+    pub fn with_explanation(mut self, explanation: &'static str) -> Self {
+        self.explanation = explanation; self
+    }
+
+    /// only run this test against `kind` proxies, and skip it for any
+    /// others, since `why` it doesn't apply to them.
+    pub fn only_for(mut self, kind: Kind, why: &'static str) -> Self {
+        self.only = Some((kind, why)); self
+    }
+
+    #[inline] pub fn reference(&self) -> Option<Reference> {
+        self.reference
+    }
+
+    #[inline] pub fn level(&self) -> Level { self.level }
+
+    #[inline] pub fn tags(&self) -> &[&'static str] { &self.tags }
+
+    #[inline] pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|&t| t == tag)
+    }
+
+    #[inline] pub fn explanation(&self) -> &'static str { self.explanation }
+
+    #[inline] pub fn only(&self) -> Option<(Kind, &'static str)> {
+        self.only
+    }
+
+    /// Returns why this test doesn't apply to a proxy deployed as
+    /// `profile`, if it doesn't.
+    pub fn skip_reason(&self, profile: &Profile) -> Option<String> {
+        match self.only {
+            Some((kind, why)) if kind != profile.kind =>
+                Some(format!("{}: {}", kind.only(), why))
+          , _ => None
+        }
+    }
+
+    /// have the upstream respond to requests for the test `id` using
+    /// `handler` while this test runs. unless the request has a
+    /// `Flossy-Test` header, its test ID is its path.
+    pub fn with_upstream<H>(mut self, id: &'static str, handler: H)
+                            -> Self
+    where H: Fn(&Received) -> Script + Send + Sync + 'static {
+        let handler: Arc<Handler> = Arc::new(handler);
+        self.upstream = Some((id, handler));
+        self
+    }
+
+    /// fill in this test's `Host` placeholders and templates with an
+    /// address that refuses connections, rather than the upstream's. the
+    /// address is reserved when the test runs.
+    pub fn with_unreachable_upstream(mut self) -> Self {
+        self.unreachable = true; self
+    }
+
+    /// wait this long for the proxy's response, rather than 10 seconds.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout; self
+    }
+
+    /// send this test's request using the given delivery strategy,
+    /// rather than in a single write.
+    pub fn with_delivery(mut self, delivery: Delivery) -> Self {
+        self.delivery = delivery; self
+    }
+
+    /// write this test's request in at most `limit` segments, even when
+    /// it's run under a delivery strategy that would split it further.
+    /// this keeps tests with very large requests from taking millions of
+    /// writes.
+    pub fn with_segment_limit(mut self, limit: usize) -> Self {
+        self.segment_limit = Some(limit); self
+    }
+
+    /// after writing this test's request, half-close or reset the
+    /// connection, rather than leaving it open.
+    pub fn with_finish(mut self, finish: Finish) -> Self {
+        self.finish = finish; self
+    }
+
+    /// before sending this test's request, send `request` to the proxy
+    /// on a connection of its own and wait for the response. setup
+    /// requests are sent in the order they were added.
+    pub fn with_setup<R>(mut self, request: R) -> Self
+    where R: Into<RawRequest> {
+        self.setup.push(request.into()); self
+    }
+
+    /// wait this long after the setup requests before sending this
+    /// test's request.
+    pub fn with_pause(mut self, pause: Duration) -> Self {
+        self.pause = Some(pause); self
+    }
+
     /// returns a future running the test against the specified proxy
-    pub fn future<'a>(&'a self, upstream_uri: &'a str, socket: TcpStream)
+    pub fn future<'a>(&'a self, upstream_uri: &'a str, socket: TcpStream,
+                      handle: Handle, delivery: &Delivery, profile: Profile)
                       -> impl Future<Item=Status, Error=Error> + 'a {
 
-        let request = self.request.clone().with_host(upstream_uri).build();
-        debug!("built request:\n{}", request);
-        let request = request.into_bytes();
-        // send the HTTP request for this test...
-        let request = io::write_all(socket, request);
+        let request = self.request.build(upstream_uri);
+        debug!("built request:\n{}", String::from_utf8_lossy(&request));
+
+        // send the HTTP request for this test, and read the response...
+        let response =
+            exchange( socket, &request, delivery, self.finish, self.timeout
+                    , handle);
 
-        // when we recieve a response, parse the response with httparse...
-        let response = request
-            .and_then(|(socket, _req)| {
-                trace!("recieved {:?}", socket);
-                io::read_to_end(socket, Vec::new())
-            });
+        // ...find out what the upstream recieved...
+        let response = response.map(move |mut exchange| {
+            if let Some((id, _)) = self.upstream {
+                exchange.upstream = upstream::received(id);
+            }
+            exchange.profile = profile;
+            exchange.authority = upstream_uri.to_owned();
+            exchange
+        });
 
         // check if the response passes this test...
         let status =
-            response.and_then(move |(_, bytes)|
-                        future::result((self.check)(bytes)) )
+            response.and_then(move |exchange|
+                        future::result((self.check)(&exchange)) )
                     //.map(|status| {
                     //    println!("{}\n", status);
                     //    status
@@ -175,28 +540,80 @@ impl Test {
     /// operator (which requires a `Result` return type)
     // TODO: there's probably a more idiomatic way to do that?
     #[inline(always)]
-    fn run_inner<'a>(&'a self, uri: &'a str, proxy_addr: &SocketAddr)
+    fn run_inner<'a>(&'a self, uri: &'a str, proxy_addr: &SocketAddr,
+                     delivery: &'a Delivery, profile: Profile)
                     -> Result<Status> {
         let mut core = Core::new()?;
-        let tcp =
-            TcpBuilder::new_v4()?
-                .reuse_address(true)?
-                .to_tcp_stream()?;
+
+        let unreachable;
+        let uri = if self.unreachable {
+            unreachable = format!("{}", upstream::unused_addr()?);
+            &unreachable[..]
+        } else {
+            uri
+        };
+
+        for setup in &self.setup {
+            let request = setup.build(uri);
+            debug!("built setup request:\n{}",
+                   String::from_utf8_lossy(&request));
+            let response = send(&mut core, proxy_addr, &request,
+                                self.timeout)?;
+            trace!("setup response:\n{}",
+                   String::from_utf8_lossy(&response.response));
+        }
+        if let Some(pause) = self.pause {
+            thread::sleep(pause);
+        }
+
+        let handle = core.handle();
         let test =
-            TcpStream::connect_stream(tcp, proxy_addr, &core.handle())
-                .and_then(move |socket| self.future(uri, socket));
+            connect(proxy_addr, &handle)?
+                .and_then(move |socket|
+                    self.future(uri, socket, handle, delivery, profile));
         core.run(test)
 
     }
 
-    /// run the test against the specified proxy
-    pub fn run<'a>(&'a self, uri: &'a str, proxy_addr: &SocketAddr)
+    /// run the test against the specified proxy, deployed as `profile`.
+    /// if `delivery` is given, it's used instead of this test's own
+    /// delivery strategy. failures of tests below `MUST` level are only
+    /// warnings, and tests that don't apply to `profile` are skipped.
+    pub fn run<'a>(&'a self, uri: &'a str, proxy_addr: &SocketAddr,
+                   delivery: Option<&'a Delivery>, profile: &Profile)
                    -> TestResult {
+        let delivery = delivery.unwrap_or(&self.delivery);
+        let limited;
+        let delivery = match self.segment_limit {
+            Some(limit) => {
+                limited = delivery.clone().with_limit(limit);
+                &limited
+            }
+          , None => delivery
+        };
+        if let Some(reason) = self.skip_reason(profile) {
+            return TestResult { name: self.name
+                              , description: self.description
+                              , delivery: delivery.clone()
+                              , status: Ok(Status::Skipped { reason: reason })
+                              }
+        }
+        if let Some((id, ref handler)) = self.upstream {
+            upstream::route(id, handler.clone());
+            upstream::forget(id);
+        }
         scoped! {
             "component" => "upstream", "test" => self.name; {
+                let status = self.run_inner(uri, proxy_addr, delivery,
+                                            *profile)
+                    .map(|status| match self.level {
+                        Level::Must => status
+                      , _ => status.warning()
+                    });
                 TestResult { name: self.name
                            , description: self.description
-                           , status: self.run_inner(uri, proxy_addr)
+                           , delivery: delivery.clone()
+                           , status: status
                            }
             }
         }
@@ -208,26 +625,37 @@ lazy_static! {
         let mut request = Request::new();
         request.with_path("/test1")
                .with_header("Connection: close");
-        Test { name: "Bad Framing 1"
-             , description: "Conflicting Content-Length headers in response"
-             , request: request
-             , check: Box::new(|response: Vec<u8>| -> Result<Status> {
-                    let mut headers = [EMPTY_HEADER; 16];
-                    let mut parsed = Response::new(&mut headers);
-                    let _ = parsed.parse(&response)
-                                  .map_err(|e| Error::new(ErrorKind::Other, e))?;
-                    let status = if let Some(502) = parsed.code {
-                        Status::Passed
-                    } else {
-                        Status::Failed {
-                            why: "Proxy response status must be 502 Bad Gateway",
-                            bytes: response.clone()
-                        }
-                    };
-
-                    Ok(status)
-                })
-        }
+        Test::new( "Bad Framing 1"
+                 , "Conflicting Content-Length headers in response"
+                 , request
+                 , expect(status_is(502),
+                          "Proxy response status must be 502 Bad Gateway"))
+            .with_upstream("/test1", |_: &Received| {
+                // multiple content length headers returned by server
+                Script::respond(Response::new()
+                    .header("Content-Length", "45")
+                    .header("Content-Length", "20")
+                    .body("aaaaa\
+                           aaaaa\
+                           aaaaa\
+                           aaaaa\
+                           aaaaa\
+                           aaaaa\0"))
+            })
+            .with_reference(7230, "3.3.3")
+            .with_tags(vec!["framing", "security"])
+            .with_explanation(
+                "The upstream sends a response with two Content-Length \
+                 headers that disagree, so there's no way to tell where its \
+                 body ends. RFC 7230 §3.3.3 says a proxy that receives such a \
+                 response must close its connection to the upstream, discard \
+                 the response and send the client 502 Bad Gateway instead.\n\
+                 \n\
+                 Forwarding the response with either length, or both, lets \
+                 whoever controls the response split it into two. A cache or \
+                 a client might then take the second part as the response to \
+                 some other request. This is response splitting, and it can \
+                 be used to poison caches.")
     };
 
     pub static ref CONFLICTING_CONTENT_LENGTH_REQ: Test = {
@@ -241,26 +669,34 @@ lazy_static! {
                            aaaaabbbbb\
                            aaaaabbbbb\
                            aaaaa");
-        Test { name: "Bad Framing 2"
-             , description: "Conflicting Content-Length headers in request"
-             , request: request
-             , check: Box::new(|response: Vec<u8>| -> Result<Status> {
-                 let mut headers = [EMPTY_HEADER; 16];
-                 let mut parsed = Response::new(&mut headers);
-                 let _ = parsed.parse(&response)
-                               .map_err(|e| Error::new(ErrorKind::Other, e))?;
-                 let status = if let Some(400) = parsed.code {
-                     Status::Passed
-                 } else {
-                     Status::Failed {
-                         why: "Proxy response status must be 400 Bad Request",
-                         bytes: response.clone()
-                     }
-                 };
-
-                 Ok(status)
-             })
-       }
+        Test::new( "Bad Framing 2"
+                 , "Conflicting Content-Length headers in request"
+                 , request
+                 , |exchange: &Exchange| one_of(exchange,
+                        &[Outcome::Status(400), Outcome::Closed],
+                        "Proxy must reject a request with conflicting \
+                         Content-Length headers"))
+            .with_upstream("/test2", |_: &Received| {
+                info!("Request should not have been recieved.");
+                Script::respond(Response::new()
+                    .body("This shouldn't have happened!"))
+            })
+            .with_reference(7230, "3.3.3")
+            .with_tags(vec!["framing", "security"])
+            .with_explanation(
+                "The request has two Content-Length headers that disagree. \
+                 RFC 7230 §3.3.3 treats that as an unrecoverable framing \
+                 error: the server must respond with 400 Bad Request and then \
+                 close the connection. A proxy that closes the connection \
+                 without responding at all is just as safe, so the test \
+                 accepts that too.\n\
+                 \n\
+                 If the proxy picks one length and the upstream picks the \
+                 other, they disagree about where the request ends. The \
+                 leftover bytes become the start of a request the proxy never \
+                 saw. This is request smuggling, and it lets an attacker get \
+                 around the proxy's access controls, or attach their request \
+                 to another client's connection.")
    };
 
    pub static ref CONFLICTING_TRANSFER_ENCOING_REQ: Test = {
@@ -275,30 +711,42 @@ lazy_static! {
                           aaaaabbbbb\
                           aaaaabbbbb");
 
-        Test { name: "Bad Framing 3"
-             , description: "Conflicting `Content-Length` and \
+        Test::new( "Bad Framing 3"
+                 , "Conflicting `Content-Length` and \
                             `Transfer-Encoding: Chunked` headers in request."
-             , request: request
-             , check: Box::new(|response: Vec<u8>| -> Result<Status> {
-                 let mut headers = [EMPTY_HEADER; 16];
-                 let mut parsed = Response::new(&mut headers);
-                 let _ = parsed.parse(&response)
-                               .map_err(|e| Error::new(ErrorKind::Other, e))?;
-
-                 let status = if let Some(200) = parsed.code {
-                     Status::Passed
-                 } else {
-                     let text = str::from_utf8(&response)
-                         .map_err(|e| Error::new(ErrorKind::Other, e))
-                         .map(String::from)?;
-                     let msg_index = text
-                         .find("Proxy must")
-                         .unwrap_or(0);
-                     Status::FailedMessage { idx: msg_index, text: text }
-                 };
-
-                 Ok(status)
-             })
-        }
+                 , request
+                 , expect(all_of(vec![
+                              upstream_received("no Content-Length header",
+                                  |request: &Received|
+                                      !request.has_header("Content-Length"))
+                            , status_is(200)
+                          ]),
+                          "Proxy must remove the Content-Length header \
+                           from a chunked request"))
+            .with_upstream("/chunked_and_content_length1",
+                           |request: &Received| {
+                if request.has_header("Content-Length") {
+                    info!("Content length headers were not removed");
+                    Script::respond(Response::new()
+                        .status_code(400, "Bad Request")
+                        .body("Proxy must remove `Content-Length` header!"))
+                } else {
+                    info!("Request was handled successfully");
+                    Script::respond(Response::new())
+                }
+            })
+            .with_reference(7230, "3.3.3")
+            .with_tags(vec!["framing", "security"])
+            .with_explanation(
+                "The request has both `Transfer-Encoding: chunked` and a \
+                 Content-Length. The chunked encoding takes precedence, and \
+                 RFC 7230 §3.3.3 says a sender must remove the Content-Length \
+                 before forwarding the message.\n\
+                 \n\
+                 An upstream that believes the Content-Length instead of the \
+                 chunked encoding will frame the request differently from the \
+                 proxy. That is the classic CL.TE request smuggling attack. \
+                 The upstream in this test answers 400 if the Content-Length \
+                 is still there when the request reaches it.")
    };
 }
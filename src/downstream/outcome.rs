@@ -0,0 +1,45 @@
+//! The ways a proxy can react to a test's request.
+//!
+//! Many requirements allow more than one correct reaction. A proxy that
+//! recieves a request with conflicting `Content-Length`s may answer 400
+//! Bad Request, or just close the connection; either way, the request
+//! never reaches the upstream. Tests like that list every outcome they
+//! accept, and report which one they saw.
+use std::fmt;
+
+use super::{End, Exchange};
+
+/// Something a proxy can do in response to a request.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// the proxy responded with this status code
+    Status(u16)
+  , /// the proxy closed or reset the connection without sending a
+    /// status line
+    Closed
+  , /// the proxy never sent the request to the upstream
+    NotForwarded
+}
+
+impl Outcome {
+    /// Returns true if the proxy did this in `exchange`.
+    pub fn observed_in(&self, exchange: &Exchange) -> bool {
+        match *self {
+            Outcome::Status(code) => exchange.status_code() == Some(code)
+          , Outcome::Closed =>
+                exchange.status_code().is_none() &&
+                (exchange.end == End::Closed || exchange.end == End::Reset)
+          , Outcome::NotForwarded => exchange.upstream.is_empty()
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Outcome::Status(code) => write!(f, "status {}", code)
+          , Outcome::Closed => write!(f, "connection closed")
+          , Outcome::NotForwarded => write!(f, "not forwarded to the upstream")
+        }
+    }
+}
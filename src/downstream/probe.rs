@@ -0,0 +1,268 @@
+//! Finding the size limits the proxy enforces on requests.
+//!
+//! Proxies limit how large a request line, a header, the header section
+//! and a body may be, but those limits are rarely documented. `probe`
+//! binary-searches each one, and notes how the proxy turns away requests
+//! that are too large: RFC 7231 §6.5.11 and §6.5.12 ask for 413 or 414,
+//! and RFC 6585 §5 for 431, but many proxies just hang up.
+use tokio_core::reactor::Core;
+
+use std::fmt;
+use std::io::Result;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use upstream::{self, Handler, Received, Response, Script};
+
+use console::{Emoji, style};
+
+use super::{End, Exchange, Request, Verb, send};
+
+/// the test ID the upstream answers probe requests on
+const PROBE_ID: &'static str = "probe";
+
+/// Something about a request that a proxy may limit the size of.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Limit {
+    /// the length of the request line, in bytes
+    RequestLine
+  , /// the length of a single header field line, in bytes
+    HeaderSize
+  , /// the total length of the header fields, in bytes
+    TotalHeaders
+  , /// the number of header fields
+    HeaderCount
+  , /// the length of the request body, in bytes
+    Body
+}
+
+impl Limit {
+    pub fn all() -> [Limit; 5] {
+        [ Limit::RequestLine
+        , Limit::HeaderSize
+        , Limit::TotalHeaders
+        , Limit::HeaderCount
+        , Limit::Body
+        ]
+    }
+
+    /// the status RFCs ask proxies to reject oversized requests with
+    pub fn expected(&self) -> u16 {
+        match *self {
+            Limit::RequestLine => 414
+          , Limit::HeaderSize | Limit::TotalHeaders | Limit::HeaderCount =>
+                431
+          , Limit::Body => 413
+        }
+    }
+
+    /// sizes known to be too small, and too large, for any proxy to
+    /// reject or accept
+    fn bounds(&self) -> (usize, usize) {
+        match *self {
+            Limit::RequestLine => (64, 1 << 20)
+          , Limit::HeaderSize => (64, 1 << 20)
+          , Limit::TotalHeaders => (64, 1 << 22)
+          , Limit::HeaderCount => (1, 1 << 16)
+          , Limit::Body => (0, 1 << 26)
+        }
+    }
+
+    /// build a request that is `size` large in the way this limit
+    /// measures
+    pub fn request(&self, size: usize) -> Request {
+        let mut request = Request::new();
+        request.with_header(format!("Flossy-Test: {}", PROBE_ID))
+               .with_header("Connection: close");
+        match *self {
+            Limit::RequestLine => {
+                // "GET " + path + " HTTP/1.1"
+                let padding = size.saturating_sub(15);
+                request.with_path(format!("/?{}", "a".repeat(padding)));
+            }
+          , Limit::HeaderSize => {
+                let padding = size.saturating_sub(16);
+                request.with_header(
+                    format!("X-Flossy-Probe: {}", "a".repeat(padding)));
+            }
+          , Limit::TotalHeaders => {
+                // split the headers into lines of at most 1024 bytes,
+                // so we don't just find the single header limit again
+                let mut remaining = size;
+                let mut i = 0;
+                while remaining > 0 {
+                    let name = format!("X-Flossy-Probe-{}: ", i);
+                    let line = remaining.min(1024).max(name.len() + 1);
+                    request.with_header(format!(
+                        "{}{}", name, "a".repeat(line - name.len())));
+                    remaining = remaining.saturating_sub(line + 2);
+                    i += 1;
+                }
+            }
+          , Limit::HeaderCount => {
+                for i in 0..size {
+                    request.with_header(format!("X-Flossy-Probe-{}: a", i));
+                }
+            }
+          , Limit::Body => {
+                request.with_verb(Verb::Post)
+                       .with_header(format!("Content-Length: {}", size))
+                       .with_body("a".repeat(size));
+            }
+        }
+        request
+    }
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match *self {
+            Limit::RequestLine => "request line"
+          , Limit::HeaderSize => "single header"
+          , Limit::TotalHeaders => "total headers"
+          , Limit::HeaderCount => "header count"
+          , Limit::Body => "body"
+        })
+    }
+}
+
+/// What the proxy did with a probe request.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// the proxy forwarded the request and the upstream's response
+    Accepted
+  , /// the proxy answered with this status instead
+    Rejected(u16)
+  , /// the proxy hung up without answering
+    Closed
+  , /// the proxy reset the connection without answering
+    Reset
+  , /// the proxy never answered
+    TimedOut
+}
+
+impl Verdict {
+    fn of(exchange: &Exchange) -> Self {
+        match exchange.status_code() {
+            Some(200) if !exchange.upstream.is_empty() => Verdict::Accepted
+          , Some(code) => Verdict::Rejected(code)
+          , None => match exchange.end {
+                End::Reset => Verdict::Reset
+              , End::TimedOut | End::Aborted => Verdict::TimedOut
+              , End::Closed => Verdict::Closed
+            }
+        }
+    }
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Verdict::Accepted => write!(f, "accepted")
+          , Verdict::Rejected(code) => write!(f, "{}", code)
+          , Verdict::Closed => write!(f, "silent close")
+          , Verdict::Reset => write!(f, "silent reset")
+          , Verdict::TimedOut => write!(f, "no response")
+        }
+    }
+}
+
+/// The result of probing one limit.
+#[derive(Clone, Debug)]
+pub struct Probed {
+    pub limit: Limit
+  , /// the largest size the proxy accepted
+    pub accepted: Option<usize>
+  , /// the smallest size the proxy rejected, and how it rejected it
+    pub rejected: Option<(usize, Verdict)>
+}
+
+impl fmt::Display for Probed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.accepted, self.rejected) {
+            (None, Some((size, verdict))) =>
+                write!( f, "{} {:<14} rejected even at {} ({})"
+                      , style(Emoji("✖️", "x")).red(), self.limit
+                      , size, verdict)
+          , (Some(size), None) =>
+                write!( f, "{} {:<14} accepted up to at least {}"
+                      , style(Emoji("❔", "?")).dim(), self.limit, size)
+          , (Some(size), Some((over, verdict))) => {
+                let expected = self.limit.expected();
+                let emoji = if verdict == Verdict::Rejected(expected) {
+                    style(Emoji("✔️", "+")).green()
+                } else {
+                    style(Emoji("⚠️", "!")).yellow()
+                };
+                write!( f, "{} {:<14} {} (at {}: {}, expected {})"
+                      , emoji, self.limit, size, over, verdict, expected)
+            }
+          , (None, None) => write!(f, "  {:<14} not probed", self.limit)
+        }
+    }
+}
+
+/// Binary-search every limit the proxy enforces, and print a report.
+pub fn probe(upstream_uri: &str, proxy_addr: &SocketAddr) -> Result<()> {
+    let handler: Arc<Handler> = Arc::new(|_: &Received| {
+        Script::respond(Response::new().body("ok"))
+    });
+    upstream::route(PROBE_ID, handler);
+
+    let mut core = Core::new()?;
+    let mut report = Vec::new();
+    for &limit in &Limit::all() {
+        let probed = scoped! {
+            "component" => "probe", "limit" => format!("{}", limit); {
+                probe_limit(&mut core, upstream_uri, proxy_addr, limit)
+            }
+        }?;
+        report.push(probed);
+    }
+
+    println!("{}", style("Request limits").bold());
+    for probed in report {
+        println!("  {}", probed);
+    }
+    Ok(())
+}
+
+/// Binary-search the largest `limit` the proxy accepts.
+pub fn probe_limit( core: &mut Core, upstream_uri: &str
+                  , proxy_addr: &SocketAddr, limit: Limit)
+                  -> Result<Probed> {
+    let mut attempt = |size: usize| -> Result<Verdict> {
+        let request = limit.request(size).to_raw().build(upstream_uri);
+        upstream::forget(PROBE_ID);
+        let mut exchange = send(core, proxy_addr, &request,
+                                Duration::from_secs(10))?;
+        exchange.upstream = upstream::received(PROBE_ID);
+        let verdict = Verdict::of(&exchange);
+        debug!("{} of {}: {}", limit, size, verdict);
+        Ok(verdict)
+    };
+
+    let (min, max) = limit.bounds();
+    let mut probed = Probed { limit: limit, accepted: None, rejected: None };
+    match attempt(min)? {
+        Verdict::Accepted => probed.accepted = Some(min)
+      , verdict => { probed.rejected = Some((min, verdict)); return Ok(probed) }
+    }
+    match attempt(max)? {
+        Verdict::Accepted => { probed.accepted = Some(max); return Ok(probed) }
+      , verdict => probed.rejected = Some((max, verdict))
+    }
+
+    // the proxy accepts `lo` and rejects `hi`
+    let (mut lo, mut hi) = (min, max);
+    while hi - lo > 1 {
+        let mid = lo + (hi - lo) / 2;
+        match attempt(mid)? {
+            Verdict::Accepted => lo = mid
+          , verdict => { hi = mid; probed.rejected = Some((mid, verdict)) }
+        }
+    }
+    probed.accepted = Some(lo);
+    Ok(probed)
+}
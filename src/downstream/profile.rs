@@ -0,0 +1,91 @@
+//! What the proxy under test is set up to do.
+//!
+//! Some requirements depend on how a proxy is deployed rather than on
+//! the RFCs alone. A reverse proxy at the edge of a network must not
+//! believe the `X-Forwarded-For` a client sends it, while one behind a
+//! trusted load balancer has to keep it. A profile tells the tests which
+//! of these the proxy is meant to be.
+use std::fmt;
+use std::str::FromStr;
+
+/// Which side of the connection the proxy acts for.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// a gateway in front of the upstream
+    Reverse
+  , /// a proxy the client has chosen to send its requests through
+    Forward
+}
+
+impl Kind {
+    /// why a test that only applies to this kind of proxy was skipped
+    pub fn only(&self) -> &'static str {
+        match *self {
+            Kind::Reverse => "only applies to reverse proxies"
+          , Kind::Forward => "only applies to forward proxies"
+        }
+    }
+}
+
+/// Whose client-identity headers the proxy is meant to believe.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Trust {
+    /// discard the `Forwarded`, `X-Forwarded-*` and `X-Real-IP` headers
+    /// clients send, and start again from the client's own address
+    Nobody
+  , /// keep the headers clients send, and append to them
+    Clients
+}
+
+/// How the proxy under test is deployed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Profile {
+    pub kind: Kind
+  , /// what the proxy does with client-identity headers
+    pub trust: Trust
+}
+
+impl Profile {
+    /// trust client-identity headers when the proxy does
+    pub fn trusting(mut self) -> Self {
+        self.trust = Trust::Clients; self
+    }
+}
+
+impl Default for Profile {
+    #[inline] fn default() -> Self {
+        Profile { kind: Kind::Reverse, trust: Trust::Nobody }
+    }
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let kind = match s {
+            "reverse" => Kind::Reverse
+          , "forward" => Kind::Forward
+          , _ => return Err(format!("unknown proxy profile {:?}", s))
+        };
+        Ok(Profile { kind: kind, ..Profile::default() })
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match *self {
+            Kind::Reverse => "reverse"
+          , Kind::Forward => "forward"
+        })
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.trust {
+            Trust::Nobody => write!(f, "{}", self.kind)
+          , Trust::Clients =>
+                write!(f, "{}, trusting forwarded headers", self.kind)
+        }
+    }
+}
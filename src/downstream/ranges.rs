@@ -0,0 +1,247 @@
+//! Tests for range requests.
+//!
+//! RFC 7233 lets a server ignore `Range` and send the whole
+//! representation, so a proxy may answer with 200 OK instead of
+//! 206 Partial Content. Whatever it sends has to be right, though: a 206
+//! must have the `Content-Range` and body the upstream sent, multiple
+//! ranges must arrive in a `multipart/byteranges` body whose boundaries
+//! are intact, and a proxy that caches partial responses must never
+//! serve one where the full representation, or a different range, was
+//! asked for (RFC 7234 §3.3).
+use upstream::{Received, Response, Script};
+
+use super::{Request, Test};
+use super::expect::{ Assertion, all_of, any_of, body_eq, expect, header_eq
+                   , header_matches, status_is };
+
+const BODY: &'static [u8] = b"abcdefghijklmnopqrstuvwxyz";
+const BOUNDARY: &'static str = "FLOSSY_BYTERANGES";
+
+/// Parse a `Range` header for a representation `len` bytes long into
+/// the inclusive ranges it asks for that can be satisfied, or `None` if
+/// it isn't a byte range request.
+fn parse_ranges(header: &[u8], len: usize) -> Option<Vec<(usize, usize)>> {
+    let header = String::from_utf8_lossy(header);
+    if !header.starts_with("bytes=") { return None }
+    let mut ranges = Vec::new();
+    for spec in header["bytes=".len()..].split(',').map(|s| s.trim()) {
+        let mut bounds = spec.splitn(2, '-');
+        let first = bounds.next().unwrap_or("");
+        let last = match bounds.next() {
+            Some(last) => last
+          , None => return None
+        };
+        let range = match (first.parse::<usize>(), last.parse::<usize>()) {
+            (Ok(first), Ok(last)) if first <= last =>
+                (first, last.min(len - 1))
+          , (Ok(first), Err(_)) if last.is_empty() => (first, len - 1)
+          , (Err(_), Ok(suffix)) if first.is_empty() && suffix > 0 =>
+                (len.saturating_sub(suffix), len - 1)
+          , _ => return None
+        };
+        if range.0 < len {
+            ranges.push(range);
+        }
+    }
+    Some(ranges)
+}
+
+/// the `multipart/byteranges` body for `ranges` of `BODY`
+fn multipart(ranges: &[(usize, usize)]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for &(first, last) in ranges {
+        body.extend(format!( "--{}\r\n\
+                              Content-Type: text/plain\r\n\
+                              Content-Range: bytes {}-{}/{}\r\n\
+                              \r\n"
+                           , BOUNDARY, first, last, BODY.len()).into_bytes());
+        body.extend_from_slice(&BODY[first..last + 1]);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend(format!("--{}--\r\n", BOUNDARY).into_bytes());
+    body
+}
+
+/// returns true if the `Content-Type` values `types` name `BOUNDARY`
+fn has_boundary(types: &[Vec<u8>]) -> bool {
+    types.iter().any(|content_type|
+        String::from_utf8_lossy(content_type).contains(BOUNDARY))
+}
+
+/// the upstream's handler for `BODY`, which honours `Range` headers
+fn resource(request: &Received) -> Script {
+    let response = Response::new()
+        .header("Content-Type", "text/plain")
+        .header("Accept-Ranges", "bytes")
+        .header("Cache-Control", "max-age=60")
+        .header("ETag", "\"alphabet\"");
+    let ranges = request.header("Range")
+                        .and_then(|range| parse_ranges(range, BODY.len()));
+    let response = match ranges {
+        None => response.body(BODY)
+      , Some(ref ranges) if ranges.is_empty() => response
+            .status_code(416, "Range Not Satisfiable")
+            .header("Content-Range", format!("bytes */{}", BODY.len()))
+      , Some(ref ranges) if ranges.len() == 1 => {
+            let (first, last) = ranges[0];
+            response
+                .status_code(206, "Partial Content")
+                .header("Content-Range",
+                        format!("bytes {}-{}/{}", first, last, BODY.len()))
+                .body(&BODY[first..last + 1])
+        }
+      , Some(ref ranges) => Response::new()
+            .status_code(206, "Partial Content")
+            .header("Content-Type", format!( "multipart/byteranges; \
+                                               boundary={}"
+                                           , BOUNDARY))
+            .header("Cache-Control", "max-age=60")
+            .header("ETag", "\"alphabet\"")
+            .body(multipart(ranges))
+    };
+    Script::respond(response)
+}
+
+/// the proxy sent the whole of `BODY` with 200 OK
+fn whole() -> Assertion {
+    all_of(vec![status_is(200), body_eq(BODY)])
+}
+
+/// the proxy sent either the whole of `BODY`, or a 206 with exactly the
+/// `content_range` and `body` the upstream would have sent
+fn range_served(content_range: &'static str, body: &'static [u8])
+                -> Assertion {
+    any_of(vec![ whole()
+               , all_of(vec![ status_is(206)
+                            , header_eq("Content-Range", content_range)
+                            , body_eq(body)
+                            ])
+               ])
+}
+
+/// returns a `GET` request for `path`, with the header line `range`
+fn ranged(path: &str, range: &str) -> Request {
+    let mut request = Request::new();
+    request.with_path(path)
+           .with_header(range)
+           .with_header("Connection: close");
+    request
+}
+
+lazy_static! {
+    pub static ref SINGLE_RANGE: Test =
+        Test::new( "Ranges 1"
+                 , "Request for a single range"
+                 , ranged("/ranges/single", "Range: bytes=0-4")
+                 , expect(range_served("bytes 0-4/26", b"abcde"),
+                          "Proxy must respond with the requested range, or the \
+                           whole representation"))
+            .with_upstream("/ranges/single", resource)
+            .with_reference(7233, "4.1")
+            .with_tags(vec!["ranges"])
+            .with_explanation(
+                "A proxy may ignore `Range` and send the whole representation \
+                 (RFC 7233 §3.1). If it sends a 206 Partial Content, though, \
+                 the Content-Range and the bytes must be exactly what the \
+                 upstream sent (RFC 7233 §4.1).");
+
+    pub static ref SUFFIX_RANGE: Test =
+        Test::new( "Ranges 2"
+                 , "Request for the last bytes of the representation"
+                 , ranged("/ranges/suffix", "Range: bytes=-5")
+                 , expect(range_served("bytes 21-25/26", b"vwxyz"),
+                          "Proxy must respond with the requested range, or the \
+                           whole representation"))
+            .with_upstream("/ranges/suffix", resource)
+            .with_reference(7233, "2.1")
+            .with_tags(vec!["ranges"])
+            .with_explanation(
+                "`bytes=-5` asks for the last five bytes, not the first (RFC \
+                 7233 §2.1). The client must get either those bytes with the \
+                 right Content-Range, or the whole representation.");
+
+    pub static ref MULTIPLE_RANGES: Test =
+        Test::new( "Ranges 3"
+                 , "Request for several ranges"
+                 , ranged("/ranges/multiple", "Range: bytes=0-2, 10-12, -2")
+                 , expect(any_of(vec![
+                              whole()
+                            , all_of(vec![
+                                  status_is(206)
+                                , header_matches("Content-Type",
+                                      "the multipart boundary in Content-Type",
+                                      has_boundary)
+                                , body_eq(multipart(&[ (0, 2), (10, 12)
+                                                     , (24, 25) ]))
+                              ])
+                          ]),
+                          "Proxy must forward a multipart/byteranges body and \
+                           its boundary unchanged"))
+            .with_upstream("/ranges/multiple", resource)
+            .with_reference(7233, "4.1")
+            .with_tags(vec!["ranges"])
+            .with_explanation(
+                "A request for several ranges gets a `multipart/byteranges` \
+                 body, separated by the boundary in the Content-Type (RFC \
+                 7233 §4.1). A proxy that changes the boundary in one place \
+                 and not the other, or re-frames the parts, leaves the client \
+                 unable to find them.");
+
+    pub static ref UNSATISFIABLE_RANGE: Test =
+        Test::new( "Ranges 4"
+                 , "Request for a range past the end of the representation"
+                 , ranged("/ranges/unsatisfiable", "Range: bytes=100-200")
+                 , expect(any_of(vec![
+                              whole()
+                            , all_of(vec![ status_is(416)
+                                         , header_eq("Content-Range",
+                                                     "bytes */26")
+                                         ])
+                          ]),
+                          "Proxy must forward a 416 Range Not Satisfiable and \
+                           its Content-Range unchanged"))
+            .with_upstream("/ranges/unsatisfiable", resource)
+            .with_reference(7233, "4.4")
+            .with_tags(vec!["ranges"])
+            .with_explanation(
+                "The range starts past the end of the representation, so the \
+                 upstream answers 416 Range Not Satisfiable, with a \
+                 Content-Range giving the representation's length (RFC 7233 \
+                 §4.4). The proxy must forward both, or send the whole \
+                 representation.");
+
+    pub static ref PARTIAL_NOT_SERVED_AS_FULL: Test =
+        Test::new( "Ranges 5"
+                 , "Request for the whole representation after a range \
+                    of it"
+                 , ranged("/ranges/then_full", "X-Flossy: whole")
+                 , expect(whole(),
+                          "Proxy must not serve a cached partial response to \
+                           a request without Range"))
+            .with_setup(ranged("/ranges/then_full", "Range: bytes=0-4"))
+            .with_upstream("/ranges/then_full", resource)
+            .with_reference(7234, "3.3")
+            .with_tags(vec!["ranges", "caching"])
+            .with_explanation(
+                "The setup request asks for a range, and the test then asks \
+                 for the whole representation. A cache that stored the 206 \
+                 must not serve it as a complete response (RFC 7234 §3.3). \
+                 That would silently truncate the representation.");
+
+    pub static ref PARTIAL_NOT_SERVED_FOR_OTHER_RANGE: Test =
+        Test::new( "Ranges 6"
+                 , "Request for a different range after a range"
+                 , ranged("/ranges/then_other", "Range: bytes=5-9")
+                 , expect(range_served("bytes 5-9/26", b"fghij"),
+                          "Proxy must respond with the requested range, or the \
+                           whole representation"))
+            .with_setup(ranged("/ranges/then_other", "Range: bytes=0-4"))
+            .with_upstream("/ranges/then_other", resource)
+            .with_reference(7234, "3.3")
+            .with_tags(vec!["ranges", "caching"])
+            .with_explanation(
+                "The setup request asks for one range, and the test then asks \
+                 for a different one. A cache must not answer with the range \
+                 it stored (RFC 7234 §3.3), since the client would get the \
+                 wrong bytes labelled with the range it asked for.");
+}
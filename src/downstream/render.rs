@@ -0,0 +1,166 @@
+//! Showing the bytes a proxy sent in failure output.
+//!
+//! A proxy that fails a test may send anything back: binary garbage,
+//! bytes that aren't UTF-8, control characters that would mangle the
+//! terminal, or a body megabytes long. Failure output shows those bytes
+//! escaped or as a hexdump, cut off after a while, and with a caret
+//! under the first byte that stopped the response from parsing.
+use httparse::{self, EMPTY_HEADER};
+
+use std::fmt::Write;
+
+/// the most bytes to show before cutting the rest off
+const LIMIT: usize = 4096;
+
+/// how many bytes a line of a hexdump shows
+const HEXDUMP_WIDTH: usize = 16;
+
+/// How failure output shows bytes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Rendering {
+    /// as text, with control characters and bytes outside ASCII escaped
+    Escaped
+  , /// as a hexdump, with offsets and the printable bytes alongside
+    Hexdump
+}
+
+impl Default for Rendering {
+    #[inline] fn default() -> Self { Rendering::Escaped }
+}
+
+/// Returns the offset of the first byte of `response` that httparse
+/// can't parse, and why, if it starts off as an HTTP response but isn't
+/// one.
+///
+/// httparse only says a response is invalid once it has seen the byte
+/// that makes it so; before that, every prefix is just incomplete. So
+/// the first invalid byte is at the end of the shortest prefix that
+/// fails to parse, which can be found with a binary search.
+pub fn divergence(response: &[u8]) -> Option<(usize, httparse::Error)> {
+    let parse = |len: usize| {
+        let mut headers = [EMPTY_HEADER; 100];
+        httparse::Response::new(&mut headers).parse(&response[..len]).err()
+    };
+    if !response.starts_with(b"HTTP/") { return None }
+    let error = match parse(response.len()) {
+        Some(error) => error
+      , None => return None
+    };
+
+    // the shortest failing prefix is in (lo, hi]
+    let (mut lo, mut hi) = (0, response.len());
+    while hi - lo > 1 {
+        let mid = lo + (hi - lo) / 2;
+        if parse(mid).is_some() { hi = mid } else { lo = mid }
+    }
+    Some((hi - 1, parse(hi).unwrap_or(error)))
+}
+
+/// `byte`, escaped if it isn't printable ASCII
+fn escape_byte(byte: u8) -> String {
+    match byte {
+        b'\r' => "\\r".to_owned()
+      , b'\n' => "\\n".to_owned()
+      , b'\t' => "\\t".to_owned()
+      , b'\\' => "\\\\".to_owned()
+      , 0x20...0x7e => (byte as char).to_string()
+      , _ => format!("\\x{:02x}", byte)
+    }
+}
+
+/// Returns `bytes` as text, with anything but printable ASCII escaped,
+/// and a line break after each `\n`.
+pub fn escape(bytes: &[u8]) -> String {
+    render(bytes, Rendering::Escaped, None)
+}
+
+/// Returns `bytes` as a hexdump.
+pub fn hexdump(bytes: &[u8]) -> String {
+    render(bytes, Rendering::Hexdump, None)
+}
+
+/// a line with a caret at `column`, explaining `why`
+fn caret(column: usize, why: &str) -> String {
+    format!("{:width$}^ {}\n", "", why, width = column)
+}
+
+/// Returns `bytes` rendered as `rendering` for failure output, cut off
+/// after the first few kilobytes, and with a caret under the byte at
+/// `mark`, explained by its message.
+fn render(bytes: &[u8], rendering: Rendering, mark: Option<(usize, &str)>)
+          -> String {
+    let shown = &bytes[..bytes.len().min(LIMIT)];
+    let mut out = String::new();
+    match rendering {
+        Rendering::Escaped => {
+            let mut line = String::new();
+            let mut caret_at = None;
+            for (i, &byte) in shown.iter().enumerate() {
+                if let Some((offset, _)) = mark {
+                    if offset == i { caret_at = Some(line.len()) }
+                }
+                line.push_str(&escape_byte(byte));
+                if byte == b'\n' || i + 1 == shown.len() {
+                    out.push_str(&line);
+                    out.push('\n');
+                    if let (Some(column), Some((_, why))) = (caret_at, mark) {
+                        out.push_str(&caret(column, why));
+                    }
+                    line.clear();
+                    caret_at = None;
+                }
+            }
+        }
+      , Rendering::Hexdump => {
+            for (n, chunk) in shown.chunks(HEXDUMP_WIDTH).enumerate() {
+                let start = n * HEXDUMP_WIDTH;
+                let _ = write!(out, "{:08x} ", start);
+                for i in 0..HEXDUMP_WIDTH {
+                    if i % 8 == 0 { out.push(' ') }
+                    match chunk.get(i) {
+                        Some(byte) => { let _ = write!(out, "{:02x} ", byte); }
+                      , None => out.push_str("   ")
+                    }
+                }
+                out.push('|');
+                out.extend(chunk.iter().map(|&byte| match byte {
+                    0x20...0x7e => byte as char
+                  , _ => '.'
+                }));
+                out.push_str("|\n");
+                if let Some((offset, why)) = mark {
+                    if offset >= start && offset < start + chunk.len() {
+                        let i = offset - start;
+                        out.push_str(&caret(10 + i * 3 + i / 8, why));
+                    }
+                }
+            }
+        }
+    }
+    if bytes.len() > shown.len() {
+        let _ = writeln!( out, "... and {} more bytes"
+                        , bytes.len() - shown.len());
+    }
+    out
+}
+
+/// Returns `response` rendered as `rendering` for failure output, with a
+/// caret under the first byte that stopped it from parsing, if there is
+/// one.
+pub fn render_response(response: &[u8], rendering: Rendering) -> String {
+    let divergence = divergence(response);
+    let why = divergence.map(|(offset, error)|
+        format!("doesn't parse from byte {}: {}", offset, error));
+    let mark = match (divergence, why.as_ref()) {
+        (Some((offset, _)), Some(why)) => Some((offset, &why[..]))
+      , _ => None
+    };
+    let mut out = render(response, rendering, mark);
+    if let (Some((offset, _)), Some(why)) = (divergence, why.as_ref()) {
+        // the caret was cut off with the rest of the bytes
+        if offset >= LIMIT {
+            let _ = writeln!(out, "(the response {})", why);
+        }
+    }
+    out
+}
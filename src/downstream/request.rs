@@ -8,68 +8,88 @@
 
 use std::default::Default;
 use std::convert;
-use std::fmt::{self, Write};
+use std::fmt;
 
-#[derive(Default, Clone)]
-pub struct Request<'a> {
+/// An HTTP request.
+///
+/// Every part of the request is owned, so requests can be put together at
+/// runtime, e.g. with headers built using `format!`.
+#[derive(Default, Clone, Debug)]
+pub struct Request {
     verb: Verb
-  , version: &'a str
-  , host: &'a str
-  , uri: &'a str
-  , headers: Vec<&'a str>
+  , version: String
+  , host: String
+  , uri: String
+  , headers: Vec<String>
   , body: Option<String>
 }
 
-impl<'a> Request<'a> {
+impl Request {
     #[inline] pub fn new() -> Self {
         Request {
-            uri: "/"
-          , version: "HTTP/1.1"
+            uri: "/".to_owned()
+          , version: "HTTP/1.1".to_owned()
           , ..Default::default()
         }
     }
 
     /// Finish building the request, returning a string
     pub fn build(&self) -> String {
-        let mut request = format!(
-            "{} {} {}\r\n\
-             Host: {}\r\n"
-          , self.verb
-          , self.uri
-          , self.version
-          , self.host
-        );
+        String::from_utf8_lossy(&self.to_raw().build(&self.host))
+            .into_owned()
+    }
+
+    /// Convert this request into a `RawRequest` with the same lines.
+    ///
+    /// If no host was set, the `Host` header becomes a placeholder that's
+    /// filled in when the raw request is built.
+    pub fn to_raw(&self) -> RawRequest {
+        let mut request_line = self.verb.as_bytes().to_vec();
+        request_line.push(b' ');
+        request_line.extend_from_slice(self.uri.as_bytes());
+        request_line.push(b' ');
+        request_line.extend_from_slice(self.version.as_bytes());
+
+        let mut raw = RawRequest::new();
+        raw.with_line(request_line);
+        if self.host.is_empty() {
+            raw.with_host_line();
+        } else {
+            raw.with_line(format!("Host: {}", self.host));
+        }
         for header in &self.headers {
-            write!(request, "{}\r\n", header)
-                .expect("Couldn't write to string!");
+            raw.with_line(header.as_str());
         }
-        write!(request, "\r\n")
-            .expect("Couldn't write to string!");
+        raw.with_line("");
 
         if let Some(ref body) = self.body {
-            write!(request, "{}\r\n", body)
-                .expect("Couldn't write message body to string?")
+            raw.with_body(body.as_bytes());
         }
 
-        request
+        raw
     }
 
     pub fn with_verb(&mut self, verb: Verb) -> &mut Self {
         self.verb = verb; self
     }
 
+    pub fn with_version<V>(&mut self, version: V) -> &mut Self
+    where V: convert::Into<String> {
+        self.version = version.into(); self
+    }
+
     pub fn with_host<H>(&mut self, host: H) -> &mut Self
-    where H: convert::Into<&'a str> {
+    where H: convert::Into<String> {
         self.host = host.into(); self
     }
 
     pub fn with_path<P>(&mut self, uri: P) -> &mut Self
-    where P: convert::Into<&'a str> {
+    where P: convert::Into<String> {
         self.uri = uri.into(); self
     }
 
     pub fn with_header<H>(&mut self, header: H) -> &mut Self
-    where H: convert::Into<&'a str> {
+    where H: convert::Into<String> {
         self.headers.push(header.into()); self
     }
 
@@ -79,23 +99,186 @@ impl<'a> Request<'a> {
     }
 }
 
+/// How a line in a `RawRequest` is terminated.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Ending { CrLf, Lf, Cr, Nothing }
+
+impl Ending {
+    #[inline] pub fn as_bytes(&self) -> &'static [u8] {
+        match *self {
+            Ending::CrLf => b"\r\n"
+          , Ending::Lf => b"\n"
+          , Ending::Cr => b"\r"
+          , Ending::Nothing => b""
+        }
+    }
+}
+
+impl Default for Ending {
+    #[inline] fn default() -> Self { Ending::CrLf }
+}
+
+#[derive(Clone, Debug)]
+enum Line { Bytes(Vec<u8>), Host, Template(Vec<u8>) }
+
+/// A request built line-by-line out of raw bytes.
+///
+/// Nothing is added that wasn't asked for: there's no implicit `Host`
+/// header, the blank line ending the head has to be added like any other
+/// line, and the body is sent exactly as given. This is for tests whose
+/// requests `Request` can't express, such as bare `LF` line endings,
+/// missing or repeated `Host` headers, or non-UTF-8 bytes.
+#[derive(Default, Clone, Debug)]
+pub struct RawRequest {
+    lines: Vec<(Line, Ending)>
+  , body: Vec<u8>
+}
+
+impl RawRequest {
+    #[inline] pub fn new() -> Self { RawRequest::default() }
+
+    /// Finish building the request. Any `Host` placeholder lines are
+    /// filled in with `host`.
+    pub fn build(&self, host: &str) -> Vec<u8> {
+        let mut request = Vec::new();
+        for &(ref line, ending) in &self.lines {
+            match *line {
+                Line::Bytes(ref bytes) => request.extend_from_slice(bytes)
+              , Line::Host => {
+                    request.extend_from_slice(b"Host: ");
+                    request.extend_from_slice(host.as_bytes());
+                }
+              , Line::Template(ref bytes) =>
+                    request.extend(fill_template(bytes, host))
+            }
+            request.extend_from_slice(ending.as_bytes());
+        }
+        request.extend_from_slice(&self.body);
+        request
+    }
+
+    /// Add a line terminated with `CRLF`.
+    pub fn with_line<L>(&mut self, line: L) -> &mut Self
+    where L: convert::Into<Vec<u8>> {
+        self.with_line_ending(line, Ending::CrLf)
+    }
+
+    pub fn with_line_ending<L>(&mut self, line: L, ending: Ending)
+                               -> &mut Self
+    where L: convert::Into<Vec<u8>> {
+        self.lines.push((Line::Bytes(line.into()), ending)); self
+    }
+
+    /// Add a `Host` header naming the upstream server, terminated with
+    /// `CRLF`. This may be added any number of times, anywhere.
+    pub fn with_host_line(&mut self) -> &mut Self {
+        self.with_host_line_ending(Ending::CrLf)
+    }
+
+    pub fn with_host_line_ending(&mut self, ending: Ending) -> &mut Self {
+        self.lines.push((Line::Host, ending)); self
+    }
+
+    /// Add a line terminated with `CRLF`, in which `{host}` is replaced
+    /// with the upstream server's address, and `{port}` with its port,
+    /// when the request is built. This is for lines that have to refer
+    /// to the upstream in other ways than a plain `Host` header, like
+    /// absolute-form request-targets.
+    pub fn with_template<L>(&mut self, line: L) -> &mut Self
+    where L: convert::Into<Vec<u8>> {
+        self.lines.push((Line::Template(line.into()), Ending::CrLf)); self
+    }
+
+    pub fn with_body<B>(&mut self, body: B) -> &mut Self
+    where B: convert::Into<Vec<u8>> {
+        self.body = body.into(); self
+    }
+}
+
+/// replace `{host}` and `{port}` in `line` with the parts of `host`
+fn fill_template(line: &[u8], host: &str) -> Vec<u8> {
+    let port = host.rsplitn(2, ':').next().unwrap_or("");
+    let mut filled = Vec::with_capacity(line.len() + host.len());
+    let mut rest = line;
+    while !rest.is_empty() {
+        if rest.starts_with(b"{host}") {
+            filled.extend_from_slice(host.as_bytes());
+            rest = &rest[6..];
+        } else if rest.starts_with(b"{port}") {
+            filled.extend_from_slice(port.as_bytes());
+            rest = &rest[6..];
+        } else {
+            filled.push(rest[0]);
+            rest = &rest[1..];
+        }
+    }
+    filled
+}
+
+impl From<Request> for RawRequest {
+    #[inline] fn from(request: Request) -> Self { request.to_raw() }
+}
+
 macro_rules! verbs {
     ($($verb:ident => $s:expr),+) => {
         /// HTTP verbs
-        #[derive(Copy, Clone, Debug)]
+        ///
+        /// This covers every method defined in RFC 7231 (plus `PATCH`), and
+        /// `Verb::Custom` for anything else: extension methods, methods
+        /// in the wrong case, or byte strings that aren't valid tokens at
+        /// all.
+        #[derive(Clone, Debug, PartialEq, Eq)]
         pub enum Verb {
             $($verb),+
+          , /// any other method, sent exactly as these bytes
+            Custom(Vec<u8>)
         }
 
-        impl fmt::Display for Verb {
-            #[inline] fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-                write!(f, "{}", match *self { $(Verb::$verb => $s),+ })
+        impl Verb {
+            /// returns the bytes this verb is sent as on the wire
+            pub fn as_bytes(&self) -> &[u8] {
+                match *self {
+                    $(Verb::$verb => $s.as_bytes()),+
+                  , Verb::Custom(ref bytes) => bytes
+                }
+            }
+
+            /// look up the verb matching `bytes`.
+            ///
+            /// methods are case-sensitive, so `b"get"` is a `Verb::Custom`
+            /// rather than `Verb::Get`.
+            pub fn from_bytes(bytes: &[u8]) -> Self {
+                $(if bytes == $s.as_bytes() { return Verb::$verb })+
+                Verb::Custom(bytes.to_vec())
             }
         }
     }
 }
 
-verbs!{ Get => "GET", Put => "PUT", Post => "POST", Delete => "DELETE" }
+verbs!{ Get => "GET"
+      , Head => "HEAD"
+      , Post => "POST"
+      , Put => "PUT"
+      , Delete => "DELETE"
+      , Connect => "CONNECT"
+      , Options => "OPTIONS"
+      , Trace => "TRACE"
+      , Patch => "PATCH"
+      }
+
+impl Verb {
+    /// a method that isn't one of the predefined verbs
+    #[inline] pub fn custom<B>(bytes: B) -> Self
+    where B: convert::Into<Vec<u8>> {
+        Verb::Custom(bytes.into())
+    }
+}
+
+impl fmt::Display for Verb {
+    #[inline] fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(self.as_bytes()))
+    }
+}
 
 impl Default for Verb {
     #[inline] fn default() -> Self { Verb::Get }
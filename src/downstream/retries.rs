@@ -0,0 +1,186 @@
+//! Tests for whether the proxy retries requests when the upstream fails.
+//!
+//! RFC 7230 §6.3.1 lets a proxy automatically retry a request whose
+//! method is idempotent, such as `GET`, `PUT` or `DELETE`, but it must
+//! not automatically retry a non-idempotent request like `POST`: the
+//! upstream may already have acted on it, and replaying its body could
+//! make it do so twice.
+//!
+//! In each test, the upstream fails the first attempt at the request and
+//! answers any later attempts with 200 OK.
+use upstream::{Received, Response, Script};
+
+use super::{Request, Test, Verb};
+use super::expect::{ Assertion, all_of, any_of, expect, status_is
+                   , upstream_requests };
+
+/// how the upstream fails the first attempt at a request
+#[derive(Copy, Clone, Debug)]
+enum Failure {
+    /// reset the connection before responding
+    Reset
+  , /// respond with 503 Service Unavailable
+    Unavailable
+}
+
+impl Failure {
+    fn script(&self) -> Script {
+        match *self {
+            Failure::Reset => Script::new().reset()
+          , Failure::Unavailable => Script::respond(Response::new()
+                .status_code(503, "Service Unavailable")
+                .body("try again later"))
+        }
+    }
+
+    /// the status the proxy should respond with if it doesn't retry
+    fn status(&self) -> u16 {
+        match *self {
+            Failure::Reset => 502
+          , Failure::Unavailable => 503
+        }
+    }
+}
+
+/// the upstream recieved the request once, and the proxy passed on
+/// `failure`
+fn not_retried(failure: Failure) -> Assertion {
+    all_of(vec![ upstream_requests("the request once",
+                     |attempts: &[Received]| attempts.len() == 1)
+               , status_is(failure.status())
+               ])
+}
+
+/// the upstream recieved the same request again, and the proxy
+/// responded with the result of the retry
+fn retried() -> Assertion {
+    all_of(vec![ upstream_requests("the same request again",
+                     |attempts: &[Received]| {
+                         attempts.len() > 1 &&
+                         attempts[1..].iter().all(|retry|
+                             retry.method == attempts[0].method &&
+                             retry.target == attempts[0].target &&
+                             retry.body == attempts[0].body)
+                     })
+               , status_is(200)
+               ])
+}
+
+/// build a test that sends a `verb` request to `path`, where the first
+/// attempt fails with `failure`
+fn retry_test( name: &'static str, description: &'static str
+             , verb: Verb, path: &'static str, failure: Failure)
+             -> Test {
+    let idempotent = verb != Verb::Post;
+    let mut request = Request::new();
+    request.with_verb(verb)
+           .with_path(path)
+           .with_header("Connection: close");
+    if !idempotent {
+        request.with_header("Content-Length: 11")
+               .with_body("hello world");
+    }
+
+    Test::new( name, description, request
+             , if idempotent {
+                   expect(any_of(vec![not_retried(failure), retried()]),
+                          "Proxy must either pass on the upstream's failure, \
+                           or retry exactly the same request")
+               } else {
+                   expect(not_retried(failure),
+                          "Proxy must not automatically retry a \
+                           non-idempotent request")
+               })
+        .with_upstream(path, move |request: &Received| {
+            if request.attempt == 1 {
+                failure.script()
+            } else {
+                Script::respond(Response::new().body("ok"))
+            }
+        })
+}
+
+lazy_static! {
+    pub static ref RETRY_GET_RESET: Test =
+        retry_test( "Retries 1", "GET after the upstream resets"
+                  , Verb::Get, "/retries/get_reset", Failure::Reset)
+            .with_reference(7230, "6.3.1")
+            .with_tags(vec!["retries", "failures"])
+            .with_explanation(
+                "The upstream resets the connection the first time it gets \
+                 the request. `GET` is idempotent, so RFC 7230 §6.3.1 lets \
+                 the proxy retry it automatically. If it does, the client \
+                 must get the response to the retry. If it doesn't, the \
+                 client must get 502 Bad Gateway.");
+
+    pub static ref RETRY_PUT_RESET: Test =
+        retry_test( "Retries 2", "PUT after the upstream resets"
+                  , Verb::Put, "/retries/put_reset", Failure::Reset)
+            .with_reference(7230, "6.3.1")
+            .with_tags(vec!["retries", "failures"])
+            .with_explanation(
+                "As Retries 1, with `PUT`. `PUT` is idempotent (RFC 7231 \
+                 §4.2.2), so repeating it leaves the resource in the same \
+                 state, and the proxy may retry it.");
+
+    pub static ref RETRY_DELETE_RESET: Test =
+        retry_test( "Retries 3", "DELETE after the upstream resets"
+                  , Verb::Delete, "/retries/delete_reset", Failure::Reset)
+            .with_reference(7230, "6.3.1")
+            .with_tags(vec!["retries", "failures"])
+            .with_explanation(
+                "As Retries 1, with `DELETE`, which is idempotent too (RFC \
+                 7231 §4.2.2).");
+
+    pub static ref RETRY_POST_RESET: Test =
+        retry_test( "Retries 4", "POST isn't retried after the upstream resets"
+                  , Verb::Post, "/retries/post_reset", Failure::Reset)
+            .with_reference(7230, "6.3.1")
+            .with_tags(vec!["retries", "failures", "security"])
+            .with_explanation(
+                "The upstream resets the connection after reading a `POST`. \
+                 It may already have acted on it, and `POST` isn't \
+                 idempotent, so RFC 7230 §6.3.1 forbids the proxy from \
+                 retrying it automatically. Sending it again could place an \
+                 order or make a payment twice. The client must get 502 Bad \
+                 Gateway, and the upstream must see the request exactly once.");
+
+    pub static ref RETRY_GET_UNAVAILABLE: Test =
+        retry_test( "Retries 5", "GET after the upstream returns 503"
+                  , Verb::Get, "/retries/get_503", Failure::Unavailable)
+            .with_reference(7230, "6.3.1")
+            .with_tags(vec!["retries", "failures"])
+            .with_explanation(
+                "The upstream answers the first `GET` with 503 Service \
+                 Unavailable. The proxy may retry the request, or forward the \
+                 503, but it must not turn it into some other error.");
+
+    pub static ref RETRY_PUT_UNAVAILABLE: Test =
+        retry_test( "Retries 6", "PUT after the upstream returns 503"
+                  , Verb::Put, "/retries/put_503", Failure::Unavailable)
+            .with_reference(7230, "6.3.1")
+            .with_tags(vec!["retries", "failures"])
+            .with_explanation(
+                "As Retries 5, with `PUT`.");
+
+    pub static ref RETRY_DELETE_UNAVAILABLE: Test =
+        retry_test( "Retries 7", "DELETE after the upstream returns 503"
+                  , Verb::Delete, "/retries/delete_503", Failure::Unavailable)
+            .with_reference(7230, "6.3.1")
+            .with_tags(vec!["retries", "failures"])
+            .with_explanation(
+                "As Retries 5, with `DELETE`.");
+
+    pub static ref RETRY_POST_UNAVAILABLE: Test =
+        retry_test( "Retries 8", "POST isn't retried after the upstream \
+                                  returns 503"
+                  , Verb::Post, "/retries/post_503", Failure::Unavailable)
+            .with_reference(7230, "6.3.1")
+            .with_tags(vec!["retries", "failures"])
+            .with_explanation(
+                "The upstream answers the first `POST` with 503 Service \
+                 Unavailable. A 503 doesn't promise that the upstream did \
+                 nothing with the request, so the proxy must not retry the \
+                 `POST` on its own (RFC 7230 §6.3.1). It must forward the \
+                 503, and leave retrying to the client.");
+}
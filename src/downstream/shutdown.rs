@@ -0,0 +1,216 @@
+//! Tests for how the proxy handles half-closed and reset connections.
+//!
+//! Either side of a TCP connection can shut down its half of it once it
+//! has nothing more to send, and still read whatever the other side
+//! sends back. A client that half-closes after its request should still
+//! get the whole response, and an upstream that half-closes after its
+//! response has still sent all of it. A reset, on the other hand, means
+//! the connection was abandoned, and the proxy must pass that on rather
+//! than making up a complete message on either side.
+use std::time::Duration;
+
+use upstream::{Received, Response, Script};
+
+use super::{Finish, Level, Outcome, Request, Test, Verb};
+use super::checks::truncation_visible;
+use super::expect::{ all_of, body_eq, complete_ok, expect, outcome
+                   , status_is, upstream_received };
+
+lazy_static! {
+    pub static ref CLIENT_HALF_CLOSE: Test = {
+        let mut request = Request::new();
+        request.with_path("/shutdown/client_half_close")
+               .with_header("Connection: close");
+        Test::new( "Shutdown 1"
+                 , "Client half-closes the connection after its request"
+                 , request
+                 , expect(complete_ok(),
+                          "Proxy should deliver the whole response after the \
+                           client half-closes"))
+            .with_finish(Finish::HalfClose)
+            .with_upstream("/shutdown/client_half_close", |_: &Received| {
+                Script::new()
+                    .delay(Duration::from_secs(1))
+                    .send(Response::new().body("hello"))
+            })
+            .with_reference(7230, "6.6")
+            .with_level(Level::Should)
+            .with_tags(vec!["connections", "shutdown"])
+            .with_explanation(
+                "The client shuts down its half of the connection after \
+                 sending its request, and waits for the response. A \
+                 half-close only means there's nothing more to send (RFC 7230 \
+                 §6.6), so the proxy should still deliver the whole response \
+                 rather than treating the request as abandoned.")
+    };
+
+    pub static ref CLIENT_HALF_CLOSE_POST: Test = {
+        let mut request = Request::new();
+        request.with_verb(Verb::Post)
+               .with_path("/shutdown/client_half_close_post")
+               .with_header("Content-Length: 11")
+               .with_header("Connection: close")
+               .with_body("hello world");
+        Test::new( "Shutdown 2"
+                 , "Client half-closes the connection after a request body"
+                 , request
+                 , expect(all_of(vec![
+                              upstream_received("the whole body",
+                                  |request: &Received|
+                                      request.body == b"hello world")
+                            , complete_ok()
+                          ]),
+                          "Proxy should forward the whole request body, and \
+                           deliver the response, after the client \
+                           half-closes"))
+            .with_finish(Finish::HalfClose)
+            .with_upstream("/shutdown/client_half_close_post", |_: &Received| {
+                Script::respond(Response::new().body("thanks"))
+            })
+            .with_reference(7230, "6.6")
+            .with_level(Level::Should)
+            .with_tags(vec!["connections", "shutdown", "bodies"])
+            .with_explanation(
+                "Like Shutdown 1, but the request has a body. The proxy \
+                 should forward all of the body and still deliver the \
+                 response, even though the client's half of the connection \
+                 closed right after the last byte of the body.")
+    };
+
+    pub static ref UPSTREAM_HALF_CLOSE: Test = {
+        let mut request = Request::new();
+        request.with_path("/shutdown/upstream_half_close")
+               .with_header("Connection: close");
+        Test::new( "Shutdown 3"
+                 , "Upstream half-closes the connection after its response"
+                 , request
+                 , expect(complete_ok(),
+                          "Proxy must forward a response the upstream \
+                           half-closed after"))
+            .with_upstream("/shutdown/upstream_half_close", |_: &Received| {
+                Script::new()
+                    .send(Response::new().body("hello"))
+                    .shutdown_write()
+            })
+            .with_reference(7230, "6.6")
+            .with_tags(vec!["connections", "shutdown"])
+            .with_explanation(
+                "The upstream shuts down its half of the connection after \
+                 sending a complete response. Nothing is missing, so the \
+                 proxy must forward the response like any other.")
+    };
+
+    pub static ref UPSTREAM_HALF_CLOSE_UNFRAMED: Test = {
+        let mut request = Request::new();
+        request.with_path("/shutdown/upstream_half_close_unframed")
+               .with_header("Connection: close");
+        Test::new( "Shutdown 4"
+                 , "Upstream half-closes the connection to end a body with \
+                    no framing"
+                 , request
+                 // a proxy that forwards the two writes as separate chunks
+                 // puts a chunk delimiter between them, which `body_eq`
+                 // removes
+                 , expect(all_of(vec![status_is(200), body_eq("hello world")]),
+                          "Proxy must forward the whole response body"))
+            .with_upstream( "/shutdown/upstream_half_close_unframed"
+                          , |_: &Received| {
+                Script::new()
+                    .send(Response::new().unframed().body("hello "))
+                    .delay(Duration::from_millis(500))
+                    .send("world")
+                    .shutdown_write()
+            })
+            .with_reference(7230, "3.3.3")
+            .with_tags(vec!["connections", "shutdown", "framing"])
+            .with_explanation(
+                "The upstream sends a response with neither Content-Length \
+                 nor Transfer-Encoding, so its body ends when the connection \
+                 closes (RFC 7230 §3.3.3). The upstream half-closes to end \
+                 it. The proxy must forward the whole body, and whatever \
+                 framing it sends the client has to cover all of it.")
+    };
+
+    pub static ref CLIENT_RESET_MID_BODY: Test = {
+        let mut request = Request::new();
+        request.with_verb(Verb::Post)
+               .with_path("/shutdown/client_reset")
+               .with_header("Content-Length: 100")
+               .with_header("Connection: close")
+               .with_body("a".repeat(50));
+        Test::new( "Shutdown 5"
+                 , "Client resets the connection part-way through a \
+                    request body"
+                 , request
+                 // the upstream only records requests it recieved in full,
+                 // so any request it saw was padded out by the proxy
+                 , expect(outcome(Outcome::NotForwarded),
+                          "Proxy must not forward a complete request when \
+                           the client aborted it"))
+            .with_finish(Finish::Reset)
+            // give the proxy time to pass the reset on
+            .with_timeout(Duration::from_secs(2))
+            .with_upstream("/shutdown/client_reset", |_: &Received| {
+                Script::respond(Response::new().body("hello"))
+            })
+            .with_reference(7230, "3.4")
+            .with_tags(vec!["connections", "shutdown", "bodies"])
+            .with_explanation(
+                "The client sends half of a request body and then resets the \
+                 connection. The request is incomplete (RFC 7230 §3.4), so \
+                 the proxy must not deliver it to the upstream as a complete \
+                 one. If the proxy padded out the body or cut down the \
+                 Content-Length, the upstream would act on a request nobody \
+                 finished sending.")
+    };
+
+    pub static ref UPSTREAM_RESET_MID_LENGTH: Test = {
+        let mut request = Request::new();
+        request.with_path("/shutdown/upstream_reset_length")
+               .with_header("Connection: close");
+        Test::new( "Shutdown 6"
+                 , "Upstream resets the connection part-way through a \
+                    Content-Length body"
+                 , request
+                 , truncation_visible())
+            .with_upstream("/shutdown/upstream_reset_length", |_: &Received| {
+                Script::new()
+                    .send(Response::new()
+                            .header("Content-Length", "100")
+                            .body(vec![b'a'; 50]))
+                    .reset()
+            })
+            .with_reference(7230, "3.4")
+            .with_tags(vec!["failures", "shutdown", "framing"])
+            .with_explanation(
+                "The upstream resets the connection part-way through a \
+                 Content-Length body. As in Upstream Failure 4, the proxy \
+                 must not present the truncated response as complete (RFC \
+                 7230 §3.4).")
+    };
+
+    pub static ref UPSTREAM_RESET_MID_CHUNKED: Test = {
+        let mut request = Request::new();
+        request.with_path("/shutdown/upstream_reset_chunked")
+               .with_header("Connection: close");
+        Test::new( "Shutdown 7"
+                 , "Upstream resets the connection part-way through a \
+                    chunked body"
+                 , request
+                 , truncation_visible())
+            .with_upstream("/shutdown/upstream_reset_chunked", |_: &Received| {
+                Script::new()
+                    .send(Response::new()
+                            .header("Transfer-Encoding", "chunked")
+                            .body("5\r\nhello\r\n"))
+                    .reset()
+            })
+            .with_reference(7230, "3.4")
+            .with_tags(vec!["failures", "shutdown", "framing"])
+            .with_explanation(
+                "The upstream resets the connection part-way through a \
+                 chunked body. As in Upstream Failure 5, the proxy must not \
+                 finish the body with a last chunk the upstream never sent \
+                 (RFC 7230 §3.4).")
+    };
+}
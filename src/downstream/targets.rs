@@ -0,0 +1,254 @@
+//! Tests for whether the proxy normalizes request-targets.
+//!
+//! RFC 7230 §5.7.2 forbids a proxy from modifying the path and query of
+//! a request-target it forwards, so percent-encoded dots and slashes,
+//! dot-segments and repeated slashes must all reach the upstream exactly
+//! as sent. A proxy that decodes or normalizes them can be made to route
+//! or authorize a request differently from how the upstream will serve
+//! it. Targets that aren't valid at all, with bytes like backslashes,
+//! `#` or `NUL`, should be answered with 400 Bad Request (RFC 7230
+//! §3.1.1), as should an asterisk-form target on any method but
+//! `OPTIONS` (RFC 7230 §5.3.4).
+//!
+//! The upstream records the exact target it recieves. Each request names
+//! its test in a `Flossy-Test` header, since its path may not survive.
+use upstream::{Received, Response, Script};
+
+use super::{Level, Outcome, RawRequest, Test};
+use super::expect::{ Assertion, all_of, any_of, complete_ok, expect
+                   , forwarded, outcome, status_is, upstream_requests };
+
+/// What the proxy must do with a test's request-target.
+#[derive(Copy, Clone, Debug)]
+enum Expect {
+    /// forward it exactly as sent, or refuse the request
+    Unchanged
+  , /// refuse the request with 400 Bad Request
+    Rejected
+}
+
+/// strip the scheme and authority from an absolute-form `target`, in
+/// case the proxy forwarded it that way
+fn origin_form(target: &[u8]) -> &[u8] {
+    let scheme_end = target.windows(3).position(|w| w == b"://");
+    let is_scheme = |scheme: &[u8]|
+        scheme.iter().all(|&b| (b as char).is_alphabetic());
+    match scheme_end {
+        Some(i) if is_scheme(&target[..i]) => {
+            let authority = &target[i + 3..];
+            let path = authority.iter()
+                .position(|&b| b == b'/')
+                .unwrap_or(authority.len());
+            &authority[path..]
+        }
+      , _ => target
+    }
+}
+
+/// the proxy refused the request without forwarding it
+fn rejected() -> Assertion {
+    all_of(vec![outcome(Outcome::NotForwarded), status_is(400)])
+}
+
+/// the upstream recieved exactly `target`, unless the proxy refused the
+/// request altogether
+fn unchanged(target: &'static [u8]) -> Assertion {
+    any_of(vec![ rejected()
+               , all_of(vec![ forwarded()
+                            , upstream_requests("the target unchanged",
+                                  move |requests: &[Received]|
+                                      requests.iter().all(|request|
+                                          origin_form(&request.target) ==
+                                          target))
+                            , complete_ok()
+                            ])
+               ])
+}
+
+/// build a test that sends a `GET` request for `target`
+fn target_test( name: &'static str, description: &'static str
+              , id: &'static str, target: &'static [u8], expected: Expect)
+              -> Test {
+    let mut request_line = b"GET ".to_vec();
+    request_line.extend_from_slice(target);
+    request_line.extend_from_slice(b" HTTP/1.1");
+    let mut request = RawRequest::new();
+    request.with_line(request_line)
+           .with_host_line()
+           .with_line(format!("Flossy-Test: {}", id))
+           .with_line("Connection: close")
+           .with_line("");
+
+    let (assertion, why) = match expected {
+        Expect::Unchanged =>
+            ( unchanged(target)
+            , "Proxy must not modify the request-target's path or query")
+      , Expect::Rejected =>
+            ( rejected()
+            , "Proxy must answer an invalid request-target with 400 Bad \
+               Request")
+    };
+
+    Test::new( name, description, request
+             , expect(assertion, why))
+        .with_upstream(id, |_: &Received| {
+            Script::respond(Response::new().body("forwarded"))
+        })
+}
+
+lazy_static! {
+    pub static ref ENCODED_DOT_SEGMENTS: Test =
+        target_test( "Targets 1", "Percent-encoded dot-segments"
+                   , "targets/encoded_dots"
+                   , b"/targets/a/%2e%2e/%2E%2E/secret", Expect::Unchanged)
+            .with_reference(7230, "5.7.2")
+            .with_tags(vec!["targets", "security"])
+            .with_explanation(
+                "The path contains `%2e%2e`, which decodes to `..`. A proxy \
+                 that decodes it and then removes the dot-segments will route \
+                 or authorize the request for a different path from the one \
+                 the upstream serves. That's a common way to reach paths a \
+                 proxy is supposed to protect. RFC 7230 §5.7.2 forbids a \
+                 proxy from modifying the path at all.");
+
+    pub static ref ENCODED_SLASH: Test =
+        target_test( "Targets 2", "Percent-encoded slash"
+                   , "targets/encoded_slash"
+                   , b"/targets/a%2Fb%2fc", Expect::Unchanged)
+            .with_reference(7230, "5.7.2")
+            .with_tags(vec!["targets", "security"])
+            .with_explanation(
+                "The path contains `%2F`, an encoded slash, which is data in \
+                 a path segment and not a separator. Decoding it changes \
+                 which segments the path has. The proxy must forward it \
+                 exactly as sent (RFC 7230 §5.7.2).");
+
+    pub static ref DOT_SEGMENTS: Test =
+        target_test( "Targets 3", "Dot-segments"
+                   , "targets/dot_segments"
+                   , b"/targets/a/./b/../../secret", Expect::Unchanged)
+            .with_reference(7230, "5.7.2")
+            .with_tags(vec!["targets", "security"])
+            .with_explanation(
+                "The path contains `.` and `..` segments. Clients normally \
+                 remove these before sending a request, but a proxy must not \
+                 do it for them. If the proxy normalizes the path while the \
+                 upstream doesn't, they disagree about which resource was \
+                 asked for (RFC 7230 §5.7.2).");
+
+    pub static ref DOUBLE_SLASHES: Test =
+        target_test( "Targets 4", "Repeated slashes"
+                   , "targets/double_slashes"
+                   , b"/targets//a///b", Expect::Unchanged)
+            .with_reference(7230, "5.7.2")
+            .with_tags(vec!["targets"])
+            .with_explanation(
+                "The path has empty segments. `//a` and `/a` are different \
+                 paths, even if many servers treat them the same. The proxy \
+                 must forward them unchanged (RFC 7230 §5.7.2).");
+
+    pub static ref QUESTION_MARKS_IN_QUERY: Test =
+        target_test( "Targets 5", "Query containing `?` and `/`"
+                   , "targets/query"
+                   , b"/targets/query?a=1?b=/../c", Expect::Unchanged)
+            .with_reference(7230, "5.7.2")
+            .with_tags(vec!["targets"])
+            .with_explanation(
+                "The query contains a second `?` and a `/../`. Both are \
+                 allowed in a query (RFC 3986 §3.4), and neither means \
+                 anything there, so the proxy must not split on the second \
+                 `?` or normalize the query like a path (RFC 7230 §5.7.2).");
+
+    pub static ref ENCODED_OVERLONG_UTF8: Test =
+        target_test( "Targets 6", "Percent-encoded overlong UTF-8"
+                   , "targets/encoded_overlong"
+                   , b"/targets/%c0%ae%c0%ae/secret", Expect::Unchanged)
+            .with_reference(7230, "5.7.2")
+            .with_tags(vec!["targets", "security"])
+            .with_explanation(
+                "The path contains `%c0%ae`, an overlong UTF-8 encoding of \
+                 `.`. Decoders that accept overlong sequences turn \
+                 `%c0%ae%c0%ae` into `..`, which has been used to escape web \
+                 roots. The proxy must forward the percent-encoding exactly \
+                 as it is (RFC 7230 §5.7.2).");
+
+    pub static ref BACKSLASHES: Test =
+        target_test( "Targets 7", "Backslashes"
+                   , "targets/backslashes"
+                   , b"/targets\\..\\secret", Expect::Rejected)
+            .with_reference(7230, "3.1.1")
+            .with_level(Level::Should)
+            .with_tags(vec!["targets", "security", "parsing"])
+            .with_explanation(
+                "Backslashes aren't allowed in a request-target, but some \
+                 servers treat them as slashes, so `\\..\\` works as a \
+                 dot-segment. RFC 7230 §3.1.1 says a server should answer an \
+                 invalid request-line with 400 Bad Request, rather than \
+                 forwarding it to an upstream that might interpret it \
+                 differently.");
+
+    pub static ref FRAGMENT: Test =
+        target_test( "Targets 8", "Fragment"
+                   , "targets/fragment"
+                   , b"/targets/page#section", Expect::Rejected)
+            .with_reference(7230, "5.3")
+            .with_level(Level::Should)
+            .with_tags(vec!["targets", "parsing"])
+            .with_explanation(
+                "A fragment is never sent in a request-target (RFC 7230 \
+                 §5.3), so a `#` in one makes the request-line invalid. It \
+                 should be answered with 400 Bad Request (RFC 7230 §3.1.1).");
+
+    pub static ref FRAGMENT_IN_QUERY: Test =
+        target_test( "Targets 9", "Query containing `#`"
+                   , "targets/query_fragment"
+                   , b"/targets/query?a=1#/../secret", Expect::Rejected)
+            .with_reference(7230, "5.3")
+            .with_level(Level::Should)
+            .with_tags(vec!["targets", "security", "parsing"])
+            .with_explanation(
+                "The query contains a `#` followed by `/../`. Parsers that \
+                 treat the `#` as the start of a fragment see a different \
+                 target from those that don't. The request-line is invalid \
+                 and should be answered with 400 Bad Request (RFC 7230 \
+                 §3.1.1).");
+
+    pub static ref RAW_OVERLONG_UTF8: Test =
+        target_test( "Targets 10", "Overlong UTF-8 bytes"
+                   , "targets/raw_overlong"
+                   , b"/targets/\xc0\xae\xc0\xae/secret", Expect::Rejected)
+            .with_reference(7230, "3.1.1")
+            .with_level(Level::Should)
+            .with_tags(vec!["targets", "security", "parsing"])
+            .with_explanation(
+                "The path contains the raw bytes of an overlong UTF-8 \
+                 encoding of `..`. Bytes outside the URI character set aren't \
+                 allowed in a request-target, so the request-line is invalid. \
+                 It should be answered with 400 Bad Request (RFC 7230 \
+                 §3.1.1).");
+
+    pub static ref NUL_IN_TARGET: Test =
+        target_test( "Targets 11", "NUL byte"
+                   , "targets/nul"
+                   , b"/targets/a\x00b", Expect::Rejected)
+            .with_reference(7230, "3.1.1")
+            .with_level(Level::Should)
+            .with_tags(vec!["targets", "security", "parsing"])
+            .with_explanation(
+                "The path contains a NUL byte. Servers written in C may see \
+                 the path end at the NUL, while the proxy checks all of it. \
+                 The request-line is invalid and should be answered with 400 \
+                 Bad Request (RFC 7230 §3.1.1).");
+
+    pub static ref ASTERISK_FORM_GET: Test =
+        target_test( "Targets 12", "Asterisk-form on a `GET` request"
+                   , "targets/asterisk"
+                   , b"*", Expect::Rejected)
+            .with_reference(7230, "5.3.4")
+            .with_level(Level::Should)
+            .with_tags(vec!["targets", "methods"])
+            .with_explanation(
+                "The asterisk-form request-target `*` is only used with \
+                 `OPTIONS` (RFC 7230 §5.3.4). A `GET *` is an invalid \
+                 request, and should be answered with 400 Bad Request.");
+}
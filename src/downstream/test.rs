@@ -31,3 +31,387 @@ fn test_request_multiple_same_header() {
      Content-Length: 20\r\n\
      \r\n")
 }
+
+#[test]
+fn test_request_verbs() {
+    let req = Request::new()
+        .with_verb(Verb::Options)
+        .with_path("*")
+        .build();
+    assert_eq!(req, "OPTIONS * HTTP/1.1\r\n\
+                     Host: \r\n\
+                     \r\n")
+}
+
+#[test]
+fn test_request_custom_verb() {
+    let req = Request::new().with_verb(Verb::custom("get")).build();
+    assert_eq!(req, "get / HTTP/1.1\r\n\
+                     Host: \r\n\
+                     \r\n")
+}
+
+#[test]
+fn test_verb_from_bytes_is_case_sensitive() {
+    assert_eq!(Verb::from_bytes(b"PATCH"), Verb::Patch);
+    assert_eq!(Verb::from_bytes(b"patch"), Verb::custom("patch"));
+}
+
+#[test]
+fn test_request_body_is_exact() {
+    let req = Request::new()
+        .with_verb(Verb::Post)
+        .with_header("Content-Length: 5")
+        .with_body("hello")
+        .build();
+    assert_eq!(req,
+    "POST / HTTP/1.1\r\n\
+     Host: \r\n\
+     Content-Length: 5\r\n\
+     \r\n\
+     hello")
+}
+
+#[test]
+fn test_raw_request_from_request() {
+    let req = Request::new()
+        .with_header("Connection: close")
+        .to_raw()
+        .build("example.com");
+    assert_eq!(req, &b"GET / HTTP/1.1\r\n\
+                       Host: example.com\r\n\
+                       Connection: close\r\n\
+                       \r\n"[..])
+}
+
+#[test]
+fn test_raw_request_line_endings() {
+    let req = RawRequest::new()
+        .with_line_ending("GET / HTTP/1.1", Ending::Lf)
+        .with_host_line_ending(Ending::Cr)
+        .with_line_ending("", Ending::Lf)
+        .build("example.com");
+    assert_eq!(req, &b"GET / HTTP/1.1\nHost: example.com\r\n"[..])
+}
+
+#[test]
+fn test_raw_request_hosts() {
+    // no Host at all
+    let req = RawRequest::new()
+        .with_line("GET / HTTP/1.1")
+        .with_line("")
+        .build("example.com");
+    assert_eq!(req, &b"GET / HTTP/1.1\r\n\r\n"[..]);
+
+    // duplicate Host headers, after another header
+    let req = RawRequest::new()
+        .with_line("GET / HTTP/1.1")
+        .with_line("Connection: close")
+        .with_host_line()
+        .with_line("Host: evil.com")
+        .with_line("")
+        .build("example.com");
+    assert_eq!(req, &b"GET / HTTP/1.1\r\n\
+                       Connection: close\r\n\
+                       Host: example.com\r\n\
+                       Host: evil.com\r\n\
+                       \r\n"[..])
+}
+
+#[test]
+fn test_raw_request_arbitrary_bytes() {
+    let req = RawRequest::new()
+        .with_line(&b"GET /\xff\x00 HTTP/1.1"[..])
+        .with_line("")
+        .with_body(&b"\xc0\xaf"[..])
+        .build("");
+    assert_eq!(req, &b"GET /\xff\x00 HTTP/1.1\r\n\r\n\xc0\xaf"[..])
+}
+
+#[test]
+fn test_request_dynamic_headers() {
+    let body = "a".repeat(12);
+    let req = Request::new()
+        .with_verb(Verb::Post)
+        .with_header(format!("Flossy-Test: {}", 7))
+        .with_header(format!("Content-Length: {}", body.len()))
+        .with_body(body)
+        .build();
+    assert_eq!(req,
+    "POST / HTTP/1.1\r\n\
+     Host: \r\n\
+     Flossy-Test: 7\r\n\
+     Content-Length: 12\r\n\
+     \r\n\
+     aaaaaaaaaaaa")
+}
+
+#[test]
+fn test_delivery_segments() {
+    let req = b"GET / HTTP/1.1\r\nHost: a\r\n\r\n";
+    assert_eq!(Delivery::whole().segments(req), vec![req.to_vec()]);
+    assert_eq!(Delivery::bytewise().segments(b"abc"),
+               vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]);
+    assert_eq!(Delivery::at(vec![20, 3, 3, 0]).segments(req),
+               vec![ b"GET".to_vec()
+                   , b" / HTTP/1.1\r\nHost".to_vec()
+                   , b": a\r\n\r\n".to_vec()
+                   ]);
+    assert_eq!(Delivery::mid_crlf().segments(b"a\r\nb\r\n"),
+               vec![b"a\r".to_vec(), b"\nb\r".to_vec(), b"\n".to_vec()]);
+    assert_eq!(Delivery::chunks().segments(b"POST / HTTP/1.1\r\n\r\n\
+                                              5;x=y\r\nhello\r\n0\r\n\r\n"),
+               vec![ b"POST / HTTP/1.1\r\n\r\n5;x=y\r\n".to_vec()
+                   , b"hello\r\n0\r\n".to_vec()
+                   , b"\r\n".to_vec()
+                   ]);
+    assert_eq!(Delivery::chunks().segments(req), vec![req.to_vec()]);
+
+    let large = vec![b'a'; 1 << 20];
+    let segments = Delivery::bytewise().with_limit(4096).segments(&large);
+    assert_eq!(segments.len(), 4096);
+    assert_eq!(segments[0], b"a".to_vec());
+    assert_eq!(segments[4095].len(), (1 << 20) - 4095);
+    assert_eq!(Delivery::bytewise().with_limit(4096).segments(b"abc"),
+               Delivery::bytewise().segments(b"abc"));
+}
+
+#[test]
+fn test_delivery_from_str() {
+    assert_eq!("crlf".parse::<Delivery>(), Ok(Delivery::mid_crlf()));
+    assert_eq!("3,20@15".parse::<Delivery>(),
+               Ok(Delivery::at(vec![3, 20])
+                    .with_delay(::std::time::Duration::from_millis(15))));
+    assert!("sideways".parse::<Delivery>().is_err());
+    assert_eq!(Delivery::bytewise()
+                   .with_delay(::std::time::Duration::from_millis(5))
+                   .to_string(),
+               "bytewise@5");
+    assert_eq!(Delivery::mid_crlf().with_limit(4096).to_string(),
+               "crlf in at most 4096 writes");
+}
+
+#[test]
+fn test_profile_from_str() {
+    assert_eq!("forward".parse::<Profile>().map(|p| p.kind),
+               Ok(Kind::Forward));
+    assert_eq!("reverse".parse::<Profile>(), Ok(Profile::default()));
+    assert!("sideways".parse::<Profile>().is_err());
+    assert_eq!(Profile::default().trusting().to_string(),
+               "reverse, trusting forwarded headers");
+}
+
+#[test]
+fn test_status_warning() {
+    let failed = Status::Failed { why: "nope", bytes: b"HTTP/1.1".to_vec() };
+    match failed.warning() {
+        Status::Warning(failed) => assert_eq!(failed.to_string(),
+            "nope\nRecieved instead:\n\nHTTP/1.1\n"),
+        other => panic!("expected a warning, got {:?}", other)
+    }
+    match Status::Passed.warning() {
+        Status::Passed => {}
+        other => panic!("expected a pass, got {:?}", other)
+    }
+}
+
+#[test]
+fn test_skipped_for_other_profiles() {
+    let test = Test::new( "Forward 1", "forward proxies only", Request::new()
+                        , |_: &Exchange| Ok(Status::Passed))
+        .only_for(Kind::Forward, "it checks something only they do");
+    let reverse = Profile::default();
+    let forward = Profile { kind: Kind::Forward, ..Profile::default() };
+    assert_eq!(test.skip_reason(&reverse),
+               Some("only applies to forward proxies: it checks something \
+                     only they do".to_owned()));
+    assert_eq!(test.skip_reason(&forward), None);
+
+    let addr = "127.0.0.1:1".parse().unwrap();
+    let result = test.run("127.0.0.1:7777", &addr, None, &reverse);
+    assert!(result.is_skipped());
+    assert!(!result.is_passed() && !result.is_warning());
+}
+
+#[test]
+fn test_find_test() {
+    let tests: &[&'static Test] =
+        &[&CONFLICTING_CONTENT_LENGTH_RESP, &UNKNOWN_METHOD];
+    assert_eq!(find_test(tests, "bad-framing-1").map(|t| t.name),
+               Some("Bad Framing 1"));
+    assert_eq!(find_test(tests, "Methods 2").map(|t| t.level()),
+               Some(Level::Should));
+    assert!(find_test(tests, "Methods 1").is_none());
+    assert!(CONFLICTING_CONTENT_LENGTH_RESP.has_tag("framing"));
+    assert_eq!(CONFLICTING_CONTENT_LENGTH_RESP.reference()
+                   .map(|r| r.to_string()),
+               Some("RFC 7230 §3.3.3".to_string()));
+}
+
+#[test]
+fn test_probe_request_sizes() {
+    let req = Limit::RequestLine.request(100).build();
+    assert_eq!(req.lines().next().unwrap().len(), 100);
+
+    let req = Limit::HeaderSize.request(200).build();
+    let header = req.lines()
+        .find(|line| line.starts_with("X-Flossy-Probe:"))
+        .unwrap();
+    assert_eq!(header.len(), 200);
+
+    let req = Limit::HeaderCount.request(5).build();
+    assert_eq!(req.lines()
+                  .filter(|line| line.starts_with("X-Flossy-Probe-"))
+                  .count(),
+               5);
+
+    let req = Limit::Body.request(10).build();
+    assert!(req.ends_with("\r\n\r\naaaaaaaaaa"));
+}
+
+#[test]
+fn test_payload_is_reproducible() {
+    use upstream::{checksum, payload};
+    assert_eq!(payload(7, 1000), payload(7, 1000));
+    assert_eq!(&payload(7, 1000)[..10], &payload(7, 10)[..]);
+    assert!(payload(7, 1000) != payload(8, 1000));
+    assert!(checksum(&payload(7, 1000)) != checksum(&payload(7, 999)));
+}
+
+#[test]
+fn test_content_codings() {
+    use upstream::{brotli, deflate, gzip};
+    let gzipped = gzip(b"hello world");
+    assert_eq!(&gzipped[..3], &[0x1f, 0x8b, 0x08][..]);
+    assert_eq!(&gzipped[gzipped.len() - 8..],
+               &[0x85, 0x11, 0x4a, 0x0d, 0x0b, 0x00, 0x00, 0x00][..]);
+    let deflated = deflate(b"hello world");
+    assert_eq!(&deflated[deflated.len() - 4..], &[0x1a, 0x0b, 0x04, 0x5d][..]);
+    let mut expected = vec![0xa0, 0x00, 0x10];
+    expected.extend_from_slice(b"hello world");
+    expected.push(0x03);
+    assert_eq!(brotli(b"hello world"), expected);
+    assert_eq!(brotli(b""), vec![0x06]);
+}
+
+#[test]
+fn test_one_of_outcomes() {
+    let mut exchange = Exchange {
+        response: Vec::new()
+      , end: End::Reset
+      , arrivals: Vec::new()
+      , elapsed: ::std::time::Duration::from_secs(0)
+      , upstream: Vec::new()
+      , profile: Profile::default()
+      , authority: String::new()
+    };
+    let acceptable = [Outcome::Status(400), Outcome::Closed];
+    match checks::one_of(&exchange, &acceptable, "rejected").unwrap() {
+        Status::Accepted(Outcome::Closed) => {}
+        other => panic!("expected the close to be accepted, got {:?}", other)
+    }
+
+    exchange.response = b"HTTP/1.1 200 OK\r\n\r\n".to_vec();
+    exchange.end = End::Closed;
+    match checks::one_of(&exchange, &acceptable, "rejected").unwrap() {
+        Status::Unexpected { expected, actual, .. } => {
+            assert_eq!(expected, "status 400 or connection closed");
+            assert_eq!(actual, "status 200");
+        }
+        other => panic!("expected a failure, got {:?}", other)
+    }
+}
+
+#[test]
+fn test_assertions() {
+    use super::expect::*;
+    let exchange = Exchange {
+        response: b"HTTP/1.1 200 OK\r\n\
+                    Content-Length: 5\r\n\
+                    \r\n\
+                    hello".to_vec()
+      , end: End::Closed
+      , arrivals: Vec::new()
+      , elapsed: ::std::time::Duration::from_secs(0)
+      , upstream: Vec::new()
+      , profile: Profile::default()
+      , authority: String::new()
+    };
+    assert_eq!(all_of(vec![status_is(200), body_eq("hello"), complete()])
+                   .check(&exchange),
+               None);
+    assert_eq!(header_absent("Content-Length").check(&exchange),
+               Some(Mismatch { expected: "no Content-Length header".into()
+                             , actual: "Content-Length: 5".into() }));
+    assert_eq!(any_of(vec![status_is(400), outcome(Outcome::Closed)])
+                   .check(&exchange),
+               Some(Mismatch { expected: "status 400 or connection closed"
+                                             .into()
+                             , actual: "status 200".into() }));
+    assert_eq!(upstream_received("anything", |_: &::upstream::Received| true)
+                   .check(&exchange)
+                   .map(|mismatch| mismatch.actual),
+               Some("nothing reached the upstream".into()));
+}
+
+#[test]
+fn test_exchange_body() {
+    let exchange = Exchange {
+        response: b"HTTP/1.1 200 OK\r\n\
+                    Transfer-Encoding: chunked\r\n\
+                    \r\n\
+                    5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n".to_vec()
+      , end: End::Closed
+      , arrivals: Vec::new()
+      , elapsed: ::std::time::Duration::from_secs(0)
+      , upstream: Vec::new()
+      , profile: Profile::default()
+      , authority: String::new()
+    };
+    assert!(exchange.is_complete());
+    assert_eq!(exchange.body(), Some(b"hello world".to_vec()));
+}
+
+#[test]
+fn test_raw_request_templates() {
+    let req = RawRequest::new()
+        .with_template("GET http://{host}/ HTTP/1.1")
+        .with_template("Host: localhost:{port}")
+        .with_line("")
+        .build("127.0.0.1:7777");
+    assert_eq!(req, b"GET http://127.0.0.1:7777/ HTTP/1.1\r\n\
+                      Host: localhost:7777\r\n\
+                      \r\n".to_vec());
+}
+
+#[test]
+fn test_render_bytes() {
+    assert_eq!(escape(b"OK\r\n\x00\xff\\"), "OK\\r\\n\n\\x00\\xff\\\\\n");
+    assert!(escape(&[b'a'; 5000]).ends_with("a\n... and 904 more bytes\n"));
+
+    let dump = hexdump(b"GET / HTTP/1.1\r\n\x1b");
+    let mut lines = dump.lines();
+    assert_eq!( lines.next()
+              , Some("00000000  47 45 54 20 2f 20 48 54  \
+                      54 50 2f 31 2e 31 0d 0a |GET / HTTP/1.1..|"));
+    let last = lines.next().unwrap();
+    assert!(last.starts_with("00000010  1b    "));
+    assert!(last.ends_with(" |.|"));
+    assert_eq!(lines.next(), None);
+}
+
+#[test]
+fn test_render_divergence() {
+    let response = b"HTTP/1.1 200 OK\r\nBad Header\r\n\r\n";
+    assert_eq!( divergence(response)
+              , Some((20, ::httparse::Error::HeaderName)));
+    assert_eq!(divergence(b"HTTP/1.1 200 OK\r\n\r\n"), None);
+    assert_eq!(divergence(b"\x16\x03\x01\x00"), None);
+    assert!(render_response(response, Rendering::Escaped)
+        .contains("Bad Header\\r\\n\n   ^ doesn't parse from byte 20"));
+
+    let status = Status::Failed { why: "Proxy must respond"
+                                , bytes: b"HTTP/1.1 \xff\xfe".to_vec() };
+    assert!(status.to_string().contains("HTTP/1.1 \\xff\\xfe"));
+    assert!(format!("{:#}", status).contains("|HTTP/1.1 ..|"));
+}
@@ -10,9 +10,6 @@ extern crate futures;
 
 extern crate tokio_io;
 extern crate tokio_core;
-extern crate tokio_proto;
-extern crate tokio_service;
-extern crate tokio_minihttp;
 
 extern crate httparse;
 extern crate net2;
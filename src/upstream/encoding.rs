@@ -0,0 +1,96 @@
+//! Content-codings for response bodies.
+//!
+//! The upstream doesn't need to compress anything, only to send bodies
+//! that are validly encoded, so these use the uncompressed block types
+//! each format has: stored blocks for `gzip` (RFC 1952) and `deflate`
+//! (RFC 1950), and uncompressed meta-blocks for `br` (RFC 7932). That
+//! keeps the encoded bytes predictable, so a proxy that decodes and
+//! re-encodes a body can't produce the same bytes by accident.
+
+/// the largest block a stored deflate block can hold
+const STORED_BLOCK: usize = 0xffff;
+/// the largest meta-block this encodes for `br`, with four length nibbles
+const BROTLI_BLOCK: usize = 0x1_0000;
+
+/// Returns the CRC-32 of `bytes`, as the gzip trailer needs it.
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, &b| {
+        (0..8).fold(crc ^ b as u32, |crc, _|
+            (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg()))
+    })
+}
+
+/// Returns the Adler-32 of `bytes`, as the zlib trailer needs it.
+fn adler32(bytes: &[u8]) -> u32 {
+    let (a, b) = bytes.iter().fold((1, 0), |(a, b), &byte| {
+        let a = (a + byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    (b << 16) | a
+}
+
+/// `bytes` as a raw deflate stream of stored blocks
+fn stored(bytes: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(bytes.len() + bytes.len() / 8192 + 5);
+    let mut blocks = bytes.chunks(STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        return vec![0x01, 0x00, 0x00, 0xff, 0xff]
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        encoded.push(if last { 0x01 } else { 0x00 });
+        encoded.extend_from_slice(&[len as u8, (len >> 8) as u8]);
+        encoded.extend_from_slice(&[!len as u8, (!len >> 8) as u8]);
+        encoded.extend_from_slice(block);
+    }
+    encoded
+}
+
+/// Returns `bytes` with the `gzip` content-coding applied.
+pub fn gzip(bytes: &[u8]) -> Vec<u8> {
+    // no flags, no modification time, unknown OS
+    let mut encoded = vec![0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00,
+                           0x00, 0xff];
+    encoded.extend(stored(bytes));
+    let crc = crc32(bytes);
+    let len = bytes.len() as u32;
+    for word in &[crc, len] {
+        encoded.extend_from_slice(&[ *word as u8, (*word >> 8) as u8
+                                   , (*word >> 16) as u8, (*word >> 24) as u8]);
+    }
+    encoded
+}
+
+/// Returns `bytes` with the `deflate` content-coding applied.
+///
+/// As RFC 7230 §4.2.2 says, this is the zlib format, not a raw deflate
+/// stream, though some servers have always sent the latter.
+pub fn deflate(bytes: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![0x78, 0x01];
+    encoded.extend(stored(bytes));
+    let adler = adler32(bytes);
+    encoded.extend_from_slice(&[ (adler >> 24) as u8, (adler >> 16) as u8
+                               , (adler >> 8) as u8, adler as u8]);
+    encoded
+}
+
+/// Returns `bytes` with the `br` content-coding applied.
+pub fn brotli(bytes: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(bytes.len() + bytes.len() / 4096 + 4);
+    for (i, block) in bytes.chunks(BROTLI_BLOCK).enumerate() {
+        // the stream header is a single 0 bit for the smallest window,
+        // which shifts the first meta-block's header along by one
+        let shift = if i == 0 { 1 } else { 0 };
+        // ISLAST = 0, MNIBBLES = 4, MLEN - 1, then ISUNCOMPRESSED = 1,
+        // padded out to a byte boundary
+        let header = (((block.len() - 1) as u32) << 3 | 1 << 19) << shift;
+        encoded.extend_from_slice(&[ header as u8, (header >> 8) as u8
+                                   , (header >> 16) as u8]);
+        encoded.extend_from_slice(block);
+    }
+    // a final, empty meta-block: ISLAST = 1, ISLASTEMPTY = 1, and the
+    // stream header too if nothing came before it
+    encoded.push(if bytes.is_empty() { 0x06 } else { 0x03 });
+    encoded
+}
@@ -0,0 +1,169 @@
+//! The upstream server that the proxy under test forwards requests to.
+//!
+//! Each route returns a `Script` describing how the upstream reacts to a
+//! request, so tests can make the upstream misbehave in specific ways.
+//! Requests are routed and recorded by their test ID (see
+//! `Received::test_id`), and every connection the upstream accepts is
+//! numbered, so tests can see which connection each request arrived on.
+//! Since scripts need control over individual socket operations, every
+//! connection is handled on its own thread using blocking IO.
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::thread;
+
+use net2::TcpBuilder;
+
+mod request;
+pub use self::request::Received;
+mod response;
+pub use self::response::*;
+mod script;
+pub use self::script::*;
+mod payload;
+pub use self::payload::*;
+mod encoding;
+pub use self::encoding::*;
+
+/// A function deciding how the upstream reacts to a request.
+pub type Handler = Fn(&Received) -> Script + Send + Sync;
+
+static NEXT_CONNECTION: AtomicUsize = ATOMIC_USIZE_INIT;
+
+lazy_static! {
+    static ref ROUTES: RwLock<HashMap<String, Arc<Handler>>> =
+        RwLock::new(HashMap::new());
+    static ref RECEIVED: Mutex<HashMap<String, Vec<Received>>> =
+        Mutex::new(HashMap::new());
+    /// sockets bound to the addresses `unused_addr` hands out
+    static ref RESERVED: Mutex<Vec<TcpBuilder>> = Mutex::new(Vec::new());
+}
+
+/// Handle requests for the test `id` with `handler`, replacing any
+/// handler previously routed there.
+pub fn route(id: &str, handler: Arc<Handler>) {
+    ROUTES.write()
+          .expect("upstream routes lock poisoned")
+          .insert(id.to_owned(), handler);
+}
+
+/// Returns every request recieved for the test `id` since it was last
+/// forgotten, in the order they arrived.
+pub fn received(id: &str) -> Vec<Received> {
+    RECEIVED.lock()
+            .expect("upstream recorder lock poisoned")
+            .get(id)
+            .cloned()
+            .unwrap_or_default()
+}
+
+/// Returns how many requests were recieved for the test `id` since it
+/// was last forgotten.
+pub fn attempts(id: &str) -> usize {
+    RECEIVED.lock()
+            .expect("upstream recorder lock poisoned")
+            .get(id)
+            .map(Vec::len)
+            .unwrap_or(0)
+}
+
+/// Forget the requests recieved for the test `id`.
+pub fn forget(id: &str) {
+    RECEIVED.lock()
+            .expect("upstream recorder lock poisoned")
+            .remove(id);
+}
+
+/// record `request`, numbering it as an attempt at its test
+fn record(request: &mut Received) {
+    let mut received = RECEIVED.lock()
+                               .expect("upstream recorder lock poisoned");
+    let attempts = received.entry(request.test_id())
+                           .or_insert_with(Vec::new);
+    request.attempt = attempts.len() + 1;
+    attempts.push(request.clone());
+}
+
+fn script_for(request: &Received) -> Script {
+    let handler = ROUTES.read()
+                        .expect("upstream routes lock poisoned")
+                        .get(&request.test_id())
+                        .cloned();
+    match handler {
+        Some(handler) => (*handler)(request)
+      , None => Script::respond(Response::new()
+                                    .status_code(404, "Not Found"))
+    }
+}
+
+/// Returns an address on the loopback interface that nothing is
+/// listening on, for tests where connecting to the upstream should fail.
+///
+/// The port stays bound, without listening, for as long as flossy runs,
+/// so nothing else can start listening on it in the meantime, and every
+/// connection to it is refused.
+pub fn unused_addr() -> io::Result<SocketAddr> {
+    let socket = TcpBuilder::new_v4()?;
+    socket.bind("127.0.0.1:0")?;
+    let addr = socket.local_addr()?;
+    RESERVED.lock()
+            .expect("upstream reserved ports lock poisoned")
+            .push(socket);
+    Ok(addr)
+}
+
+pub fn serve(addr: SocketAddr) {
+    scoped! { "component" => "upstream", "address" => format!("{}", addr);
+         {
+            info!("starting...");
+            let listener = TcpListener::bind(addr)
+                .expect("upstream couldn't bind to address");
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let id = NEXT_CONNECTION.fetch_add(1, Ordering::SeqCst);
+                        thread::spawn(move || connection(stream, id));
+                    }
+                  , Err(e) => warn!("couldn't accept connection: {}", e)
+                }
+            }
+        }
+    }
+}
+
+/// handle every request on a connection from the proxy, until either
+/// the proxy or a script closes it
+fn connection(mut stream: TcpStream, id: usize) {
+    let peer = stream.peer_addr()
+                     .map(|addr| format!("{}", addr))
+                     .unwrap_or_default();
+    scoped! { "component" => "upstream", "peer" => peer
+            , "connection" => id; {
+        let mut buf = Vec::new();
+        let mut stalled = false;
+        loop {
+            let mut request = match request::read(&mut stream, &mut buf) {
+                Ok(Some(request)) => request
+              , Ok(None) => { debug!("connection closed by proxy"); return }
+              , Err(e) => { info!("couldn't read request: {}", e); return }
+            };
+            request.connection = id;
+            trace!("{:?}", request);
+            record(&mut request);
+            // once a script has stalled the connection, nothing more is
+            // sent on it, but later requests are still recorded, so a
+            // retry on the same connection is seen
+            if stalled { continue }
+
+            let script = script_for(&request);
+            stalled = script.stalls();
+            match script.run(&mut stream) {
+                Ok(true) => continue
+              , Ok(false) => { debug!("connection closed by script"); return }
+              , Err(e) => { info!("couldn't run script: {}", e); return }
+            }
+        }
+    }}
+}
@@ -0,0 +1,34 @@
+//! Large, reproducible message bodies.
+//!
+//! Bodies are generated from a seeded PRNG, so both ends of a test can
+//! produce the same bytes without sending them to each other, and
+//! compared by checksum, so the recorder doesn't need to keep them.
+
+/// Returns `len` pseudo-random bytes generated from `seed`.
+///
+/// The same seed always produces the same bytes, and a body generated
+/// with a longer `len` starts with the bytes of a shorter one.
+pub fn payload(seed: u64, len: usize) -> Vec<u8> {
+    // xorshift64*, which is plenty for making bodies that won't survive
+    // being truncated, reordered or re-encoded unnoticed
+    let mut state = seed ^ 0x9E37_79B9_7F4A_7C15;
+    if state == 0 { state = 1 }
+    let mut bytes = Vec::with_capacity(len + 8);
+    while bytes.len() < len {
+        state ^= state >> 12;
+        state ^= state << 25;
+        state ^= state >> 27;
+        let word = state.wrapping_mul(0x2545_F491_4F6C_DD1D);
+        for i in 0..8 {
+            bytes.push((word >> (i * 8)) as u8);
+        }
+    }
+    bytes.truncate(len);
+    bytes
+}
+
+/// Returns the 64-bit FNV-1a hash of `bytes`.
+pub fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b|
+        (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3))
+}
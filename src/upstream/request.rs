@@ -0,0 +1,232 @@
+//! Reading requests forwarded by the proxy.
+//!
+//! This is deliberately lenient: we want to see exactly what the proxy
+//! sent us, even when it's malformed, so the parser only does as much as
+//! is needed to find where each request ends.
+use std::io::{self, Read};
+use std::net::TcpStream;
+use std::str;
+
+use super::checksum;
+
+/// A request recieved from the proxy.
+#[derive(Clone, Debug, Default)]
+pub struct Received {
+    /// the request method, exactly as sent
+    pub method: Vec<u8>
+  , /// the request-target, exactly as sent
+    pub target: Vec<u8>
+  , /// the HTTP version, exactly as sent
+    pub version: Vec<u8>
+  , /// the header fields, in the order they were sent
+    pub headers: Vec<(String, Vec<u8>)>
+  , /// the header lines exactly as sent, without their line endings
+    pub raw_headers: Vec<Vec<u8>>
+  , /// the message body, with any chunked encoding removed
+    pub body: Vec<u8>
+  , /// the checksum of `body`
+    pub checksum: u64
+  , /// how many requests with this test ID the upstream had recieved,
+    /// including this one
+    pub attempt: usize
+  , /// the number of the connection this request arrived on
+    pub connection: usize
+}
+
+impl Received {
+    /// the request-target with any query string removed
+    pub fn path(&self) -> String {
+        let path = self.target.split(|&b| b == b'?')
+                              .next()
+                              .unwrap_or(&[]);
+        String::from_utf8_lossy(path).into_owned()
+    }
+
+    /// The ID of the test this request belongs to: the value of the
+    /// `Flossy-Test` header if there is one, or the path otherwise.
+    ///
+    /// Tests whose request-target the proxy might rewrite should send a
+    /// `Flossy-Test` header, so the upstream can still tell which test
+    /// the request is for.
+    pub fn test_id(&self) -> String {
+        match self.header("Flossy-Test") {
+            Some(id) => String::from_utf8_lossy(id).into_owned()
+          , None => self.path()
+        }
+    }
+
+    /// returns the values of every header named `name`, ignoring case
+    pub fn headers_named<'a, 'n>(&'a self, name: &'n str)
+                                 -> impl Iterator<Item=&'a [u8]> + 'n
+    where 'a: 'n {
+        self.headers.iter()
+            .filter(move |&&(ref n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, ref value)| &value[..])
+    }
+
+    /// returns the value of the first header named `name`, ignoring case
+    #[inline] pub fn header(&self, name: &str) -> Option<&[u8]> {
+        self.headers_named(name).next()
+    }
+
+    #[inline] pub fn has_header(&self, name: &str) -> bool {
+        self.header(name).is_some()
+    }
+
+    fn is_chunked(&self) -> bool {
+        self.headers_named("Transfer-Encoding")
+            .any(|value| String::from_utf8_lossy(value)
+                            .to_lowercase()
+                            .contains("chunked"))
+    }
+
+    fn content_length(&self) -> io::Result<usize> {
+        self.header("Content-Length")
+            .map(|value| str::from_utf8(value).ok()
+                            .and_then(|value| value.trim().parse().ok())
+                            .ok_or_else(|| invalid("invalid Content-Length")))
+            .unwrap_or(Ok(0))
+    }
+}
+
+#[inline] fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Read the next request from `stream`.
+///
+/// `buf` holds any bytes that were read past the end of the previous
+/// request. Returns `Ok(None)` if the connection was closed cleanly
+/// before another request began.
+pub fn read(stream: &mut TcpStream, buf: &mut Vec<u8>)
+            -> io::Result<Option<Received>> {
+    // servers should ignore empty lines before the request line
+    // (RFC 7230 §3.5)
+    loop {
+        let blank = buf.iter().take_while(|&&b| b == b'\r' || b == b'\n')
+                              .count();
+        buf.drain(..blank);
+        if !buf.is_empty() { break }
+        if fill(stream, buf)? == 0 { return Ok(None) }
+    }
+
+    let len = loop {
+        if let Some(len) = head_len(buf) { break len }
+        if fill(stream, buf)? == 0 {
+            return Err(io::Error::new( io::ErrorKind::UnexpectedEof
+                                     , "connection closed mid-request"))
+        }
+    };
+    let head = buf.drain(..len).collect::<Vec<u8>>();
+    let mut request = parse_head(&head);
+
+    request.body = if request.is_chunked() {
+        read_chunked(stream, buf)?
+    } else {
+        let len = request.content_length()?;
+        take(stream, buf, len)?
+    };
+    request.checksum = checksum(&request.body);
+
+    Ok(Some(request))
+}
+
+/// read more bytes from `stream` into `buf`, returning how many were read
+fn fill(stream: &mut TcpStream, buf: &mut Vec<u8>) -> io::Result<usize> {
+    let mut chunk = [0; 4096];
+    let n = stream.read(&mut chunk)?;
+    buf.extend_from_slice(&chunk[..n]);
+    Ok(n)
+}
+
+/// remove exactly `len` bytes from the front of `buf`, reading more
+/// from `stream` if necessary
+fn take(stream: &mut TcpStream, buf: &mut Vec<u8>, len: usize)
+        -> io::Result<Vec<u8>> {
+    while buf.len() < len {
+        if fill(stream, buf)? == 0 {
+            return Err(io::Error::new( io::ErrorKind::UnexpectedEof
+                                     , "connection closed mid-body"))
+        }
+    }
+    Ok(buf.drain(..len).collect())
+}
+
+/// remove a line from the front of `buf`, without its line ending
+fn take_line(stream: &mut TcpStream, buf: &mut Vec<u8>)
+             -> io::Result<Vec<u8>> {
+    loop {
+        if let Some(i) = buf.iter().position(|&b| b == b'\n') {
+            let mut line = buf.drain(..i + 1).collect::<Vec<u8>>();
+            line.pop();
+            if line.last() == Some(&b'\r') { line.pop(); }
+            return Ok(line)
+        }
+        if fill(stream, buf)? == 0 {
+            return Err(io::Error::new( io::ErrorKind::UnexpectedEof
+                                     , "connection closed mid-line"))
+        }
+    }
+}
+
+fn read_chunked(stream: &mut TcpStream, buf: &mut Vec<u8>)
+                -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line = take_line(stream, buf)?;
+        let size = line.split(|&b| b == b';').next().unwrap_or(&[]);
+        let size = str::from_utf8(size).ok()
+            .and_then(|size| usize::from_str_radix(size.trim(), 16).ok())
+            .ok_or_else(|| invalid("invalid chunk size"))?;
+        if size == 0 { break }
+        body.extend(take(stream, buf, size)?);
+        take_line(stream, buf)?;
+    }
+    // skip any trailer fields
+    while !take_line(stream, buf)?.is_empty() { }
+    Ok(body)
+}
+
+/// returns the length of the head at the start of `buf`, including the
+/// empty line that ends it, if the whole head has been read
+fn head_len(buf: &[u8]) -> Option<usize> {
+    buf.windows(2)
+       .enumerate()
+       .find(|&(i, window)|
+            window == b"\n\n" ||
+            window == b"\r\n" && buf[i + 2..].starts_with(b"\r\n"))
+       .map(|(i, window)| if window == b"\n\n" { i + 2 } else { i + 4 })
+}
+
+fn parse_head(head: &[u8]) -> Received {
+    let mut lines = head.split(|&b| b == b'\n')
+                        .map(|line| if line.last() == Some(&b'\r') {
+                            &line[..line.len() - 1]
+                        } else {
+                            line
+                        });
+
+    let mut request = Received::default();
+    if let Some(request_line) = lines.next() {
+        let mut parts = request_line.splitn(3, |&b| b == b' ');
+        request.method = parts.next().unwrap_or(&[]).to_vec();
+        request.target = parts.next().unwrap_or(&[]).to_vec();
+        request.version = parts.next().unwrap_or(&[]).to_vec();
+    }
+
+    for line in lines.filter(|line| !line.is_empty()) {
+        request.raw_headers.push(line.to_vec());
+        let colon = line.iter().position(|&b| b == b':')
+                        .unwrap_or(line.len());
+        let name = String::from_utf8_lossy(&line[..colon]).into_owned();
+        let value = line.get(colon + 1..).unwrap_or(&[]);
+        let start = value.iter().position(|&b| b != b' ' && b != b'\t')
+                         .unwrap_or(value.len());
+        let end = value.iter().rposition(|&b| b != b' ' && b != b'\t')
+                       .map(|i| i + 1)
+                       .unwrap_or(start);
+        request.headers.push((name, value[start..end].to_vec()));
+    }
+
+    request
+}
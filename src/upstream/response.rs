@@ -0,0 +1,83 @@
+//! Building the responses that the upstream sends back to the proxy.
+
+/// An HTTP response.
+///
+/// Unless `unframed` is called, a `Content-Length` header is added when
+/// the response is built if it doesn't already have a `Content-Length`
+/// or `Transfer-Encoding` header and its status allows a body.
+#[derive(Clone, Debug)]
+pub struct Response {
+    code: u16
+  , status: String
+  , headers: Vec<(String, Vec<u8>)>
+  , body: Vec<u8>
+  , framed: bool
+}
+
+impl Response {
+    #[inline] pub fn new() -> Self {
+        Response { code: 200
+                 , status: "HTTP/1.1 200 OK".to_owned()
+                 , headers: Vec::new()
+                 , body: Vec::new()
+                 , framed: true
+                 }
+    }
+
+    pub fn status_code(mut self, code: u16, message: &str) -> Self {
+        self.code = code;
+        self.status = format!("HTTP/1.1 {} {}", code, message);
+        self
+    }
+
+    pub fn header<V>(mut self, name: &str, value: V) -> Self
+    where V: AsRef<[u8]> {
+        self.headers.push((name.to_owned(), value.as_ref().to_vec()));
+        self
+    }
+
+    pub fn body<B>(mut self, body: B) -> Self
+    where B: Into<Vec<u8>> {
+        self.body = body.into(); self
+    }
+
+    /// don't add a `Content-Length` header when building the response
+    pub fn unframed(mut self) -> Self {
+        self.framed = false; self
+    }
+
+    fn has_header(&self, name: &str) -> bool {
+        self.headers.iter().any(|&(ref n, _)| n.eq_ignore_ascii_case(name))
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        let needs_length = self.framed
+            && !(self.code < 200 || self.code == 204 || self.code == 304)
+            && !self.has_header("Content-Length")
+            && !self.has_header("Transfer-Encoding");
+
+        let mut bytes = self.status.into_bytes();
+        bytes.extend_from_slice(b"\r\n");
+        for (name, value) in self.headers {
+            bytes.extend_from_slice(name.as_bytes());
+            bytes.extend_from_slice(b": ");
+            bytes.extend_from_slice(&value);
+            bytes.extend_from_slice(b"\r\n");
+        }
+        if needs_length {
+            bytes.extend_from_slice(
+                format!("Content-Length: {}\r\n", self.body.len()).as_bytes());
+        }
+        bytes.extend_from_slice(b"\r\n");
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+}
+
+impl Default for Response {
+    #[inline] fn default() -> Self { Response::new() }
+}
+
+impl From<Response> for Vec<u8> {
+    #[inline] fn from(response: Response) -> Self { response.into_bytes() }
+}
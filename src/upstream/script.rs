@@ -0,0 +1,108 @@
+//! Scripts describing how the upstream reacts to a request.
+//!
+//! A well-behaved origin server isn't very interesting to test a proxy
+//! against, so rather than just returning a response, each route returns
+//! a script of socket operations, which lets it send partial responses,
+//! hang up part-way through, or never answer at all.
+use net2::TcpStreamExt;
+
+use std::io::{self, Write};
+use std::net::{Shutdown, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Clone, Debug)]
+pub enum Action {
+    /// write these bytes to the proxy
+    Send(Vec<u8>)
+  , /// wait before doing anything else
+    Delay(Duration)
+  , /// close the connection cleanly
+    Close
+  , /// close the connection with a TCP RST
+    Reset
+  , /// shut down our half of the connection, but keep reading from
+    /// the proxy
+    ShutdownWrite
+  , /// never respond to this request, or any later one on the same
+    /// connection, but keep reading and recording requests until the
+    /// proxy closes it
+    Stall
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Script {
+    actions: Vec<Action>
+}
+
+impl Script {
+    #[inline] pub fn new() -> Self { Script::default() }
+
+    /// a script that sends `response` and then waits for the next request
+    #[inline] pub fn respond<R>(response: R) -> Self
+    where R: Into<Vec<u8>> {
+        Script::new().send(response)
+    }
+
+    pub fn send<B>(mut self, bytes: B) -> Self
+    where B: Into<Vec<u8>> {
+        self.actions.push(Action::Send(bytes.into())); self
+    }
+
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.actions.push(Action::Delay(delay)); self
+    }
+
+    pub fn close(mut self) -> Self {
+        self.actions.push(Action::Close); self
+    }
+
+    pub fn reset(mut self) -> Self {
+        self.actions.push(Action::Reset); self
+    }
+
+    pub fn shutdown_write(mut self) -> Self {
+        self.actions.push(Action::ShutdownWrite); self
+    }
+
+    pub fn stall(mut self) -> Self {
+        self.actions.push(Action::Stall); self
+    }
+
+    /// whether the script stalls the connection it runs on
+    pub fn stalls(&self) -> bool {
+        self.actions.iter().any(|action| match *action {
+            Action::Stall => true
+          , _ => false
+        })
+    }
+
+    /// Run the script against `stream`, returning whether the connection
+    /// is still open afterwards.
+    pub fn run(&self, stream: &mut TcpStream) -> io::Result<bool> {
+        for action in &self.actions {
+            trace!("{:?}", action);
+            match *action {
+                Action::Send(ref bytes) => {
+                    stream.write_all(bytes)?;
+                    stream.flush()?;
+                }
+              , Action::Delay(delay) => thread::sleep(delay)
+              , Action::Close => {
+                    stream.shutdown(Shutdown::Both)?;
+                    return Ok(false)
+                }
+              , Action::Reset => {
+                    // with a zero linger timeout, closing the socket
+                    // sends a RST rather than a FIN.
+                    let linger = Some(Duration::from_secs(0));
+                    TcpStreamExt::set_linger(&*stream, linger)?;
+                    return Ok(false)
+                }
+              , Action::ShutdownWrite => stream.shutdown(Shutdown::Write)?
+              , Action::Stall => return Ok(true)
+            }
+        }
+        Ok(true)
+    }
+}